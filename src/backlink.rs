@@ -0,0 +1,152 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::jump_definition::{link_at, resolve_link, resolve_relative};
+use crate::neo_ast::Document;
+use crate::workspace::WorkspaceIndex;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImCompleteBacklink {
+    pub path: PathBuf,
+    // The whole `<<...>>` tag
+    pub span: Range<usize>,
+    // Just the id or path inside the tag
+    pub target_span: Range<usize>,
+    pub text: String,
+}
+
+// The page the cursor is talking about. That's the
+// page itself when the cursor is on its metadata id
+// or its title, or the target when it's on a link.
+pub fn page_at(
+    document: &Document,
+    path: &Path,
+    offset: usize,
+    index: &WorkspaceIndex,
+) -> Option<PathBuf> {
+    let contains = |span: &Range<usize>| span.start <= offset && offset <= span.end;
+    let on_id = document.metadata_id().is_some_and(|id| contains(&id.span));
+    let on_title = document.title().is_some_and(|title| contains(&title.span));
+    if on_id || on_title {
+        return Some(path.to_path_buf());
+    }
+    let target = link_at(document, offset)?.link_target()?.text();
+    resolve_link(&target, path, index)
+}
+
+// Every link in the workspace that points at `page`,
+// either by its metadata id or by a relative path
+pub fn backlinks(page: &Path, index: &WorkspaceIndex) -> Vec<ImCompleteBacklink> {
+    let id = index
+        .get(page)
+        .and_then(|target| target.document.metadata_id().map(|id| id.text.clone()));
+    let mut response = index
+        .pages()
+        .into_iter()
+        .flat_map(|path| {
+            let Some(source) = index.get(&path) else {
+                return vec![];
+            };
+            source
+                .document
+                .inline_tags()
+                .into_iter()
+                .filter_map(|tag| {
+                    let target = tag.link_target()?;
+                    let text = target.text();
+                    let matches = Some(&text) == id.as_ref()
+                        || resolve_relative(&text, &path).as_deref() == Some(page);
+                    matches.then(|| ImCompleteBacklink {
+                        path: path.clone(),
+                        span: tag.span.clone(),
+                        target_span: target.span.clone(),
+                        text: tag.text().map(|p| p.text()).unwrap_or_default(),
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    response.sort_by(|a, b| a.path.cmp(&b.path).then(a.span.start.cmp(&b.span.start)));
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nom_parser::nom_parse;
+    use crate::registry::SectionRegistry;
+    use pretty_assertions::assert_eq;
+
+    fn workspace() -> WorkspaceIndex {
+        let registry = SectionRegistry::default();
+        let index = WorkspaceIndex::default();
+        index.insert(
+            PathBuf::from("/site/alfa.neo"),
+            "-- title\n\nAlfa\n\n-- metadata\n-- id: 2w3eqltz",
+            &registry,
+        );
+        index.insert(
+            PathBuf::from("/site/bravo.neo"),
+            "-- p\n\nsee <<link|alfa|2w3eqltz>>",
+            &registry,
+        );
+        index.insert(
+            PathBuf::from("/site/posts/charlie.neo"),
+            "-- p\n\n<<link|the alfa page|../alfa.neo>> and <<link|bravo|../bravo.neo>>",
+            &registry,
+        );
+        index
+    }
+
+    #[test]
+    pub fn test_backlinks() {
+        let index = workspace();
+        let left = vec![
+            ImCompleteBacklink {
+                path: PathBuf::from("/site/bravo.neo"),
+                span: 10..32,
+                target_span: 22..30,
+                text: "alfa".to_string(),
+            },
+            ImCompleteBacklink {
+                path: PathBuf::from("/site/posts/charlie.neo"),
+                span: 6..40,
+                target_span: 27..38,
+                text: "the alfa page".to_string(),
+            },
+        ];
+        assert_eq!(left, backlinks(Path::new("/site/alfa.neo"), &index));
+    }
+
+    #[test]
+    pub fn test_page_at() {
+        let index = workspace();
+        let alfa = Path::new("/site/alfa.neo");
+        let document = nom_parse(
+            "-- title\n\nAlfa\n\n-- metadata\n-- id: 2w3eqltz",
+            &SectionRegistry::default(),
+        )
+        .unwrap()
+        .1;
+        assert_eq!(
+            Some(alfa.to_path_buf()),
+            page_at(&document, alfa, 36, &index)
+        );
+        assert_eq!(
+            Some(alfa.to_path_buf()),
+            page_at(&document, alfa, 11, &index)
+        );
+        let bravo = Path::new("/site/bravo.neo");
+        let document = nom_parse(
+            "-- p\n\nsee <<link|alfa|2w3eqltz>>",
+            &SectionRegistry::default(),
+        )
+        .unwrap()
+        .1;
+        assert_eq!(
+            Some(alfa.to_path_buf()),
+            page_at(&document, bravo, 25, &index)
+        );
+        assert_eq!(None, page_at(&document, bravo, 2, &index));
+    }
+}
@@ -1,19 +1,11 @@
+#![allow(clippy::result_large_err, clippy::type_complexity)]
 use chumsky::Parser;
 use chumsky::{prelude::*, stream::Stream};
 use core::fmt;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use tower_lsp::lsp_types::SemanticTokenType;
-
-use crate::semantic_token::LEGEND_TYPE;
 
 pub type Span = std::ops::Range<usize>;
-#[derive(Debug)]
-pub struct ImCompleteSemanticToken {
-    pub start: usize,
-    pub length: usize,
-    pub token_type: usize,
-}
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Token {
     Null,
@@ -558,143 +550,21 @@ pub fn type_inference(expr: &Spanned<Expr>, symbol_type_table: &mut HashMap<Span
     }
 }
 
-pub fn _parse(
-    src: &str,
-) -> (
-    Option<HashMap<String, Func>>,
-    Vec<Simple<String>>,
-    Vec<ImCompleteSemanticToken>,
-) {
-    let (tokens, _errs) = lexer().parse_recovery(src);
-
-    let (ast, _tokenize_errors, semantic_tokens) = if let Some(tokens) = tokens {
-        // info!("Tokens = {:?}", tokens);
-        let semantic_tokens = tokens
-            .iter()
-            .filter_map(|(token, span)| match token {
-                Token::Null => None,
-                Token::Bool(_) => None,
-                Token::Num(_) => Some(ImCompleteSemanticToken {
-                    start: span.start,
-                    length: span.len(),
-                    token_type: LEGEND_TYPE
-                        .iter()
-                        .position(|item| item == &SemanticTokenType::NUMBER)
-                        .unwrap(),
-                }),
-                Token::Str(_) => Some(ImCompleteSemanticToken {
-                    start: span.start,
-                    length: span.len(),
-                    token_type: LEGEND_TYPE
-                        .iter()
-                        .position(|item| item == &SemanticTokenType::KEYWORD)
-                        .unwrap(),
-                }),
-                Token::Op(_) => Some(ImCompleteSemanticToken {
-                    start: span.start,
-                    length: span.len(),
-                    token_type: LEGEND_TYPE
-                        .iter()
-                        .position(|item| item == &SemanticTokenType::OPERATOR)
-                        .unwrap(),
-                }),
-                Token::Ctrl(_) => None,
-                Token::Ident(_) => None,
-                Token::Fn => Some(ImCompleteSemanticToken {
-                    start: span.start,
-                    length: span.len(),
-                    token_type: LEGEND_TYPE
-                        .iter()
-                        .position(|item| item == &SemanticTokenType::KEYWORD)
-                        .unwrap(),
-                }),
-                Token::Let => Some(ImCompleteSemanticToken {
-                    start: span.start,
-                    length: span.len(),
-                    token_type: LEGEND_TYPE
-                        .iter()
-                        .position(|item| item == &SemanticTokenType::KEYWORD)
-                        .unwrap(),
-                }),
-                Token::Print => Some(ImCompleteSemanticToken {
-                    start: span.start,
-                    length: span.len(),
-                    token_type: LEGEND_TYPE
-                        .iter()
-                        .position(|item| item == &SemanticTokenType::FUNCTION)
-                        .unwrap(),
-                }),
-                Token::If => Some(ImCompleteSemanticToken {
-                    start: span.start,
-                    length: span.len(),
-                    token_type: LEGEND_TYPE
-                        .iter()
-                        .position(|item| item == &SemanticTokenType::KEYWORD)
-                        .unwrap(),
-                }),
-                Token::Else => Some(ImCompleteSemanticToken {
-                    start: span.start,
-                    length: span.len(),
-                    token_type: LEGEND_TYPE
-                        .iter()
-                        .position(|item| item == &SemanticTokenType::KEYWORD)
-                        .unwrap(),
-                }),
-            })
-            .collect::<Vec<_>>();
-
-        let len = src.chars().count();
-
-        let (ast, parse_errs) =
-            funcs_parser().parse_recovery(Stream::from_iter(len..len + 1, tokens.into_iter()));
-
-        // println!("{:#?}", ast);
-        // if let Some(funcs) = ast.filter(|_| errs.len() + parse_errs.len() == 0) {
-        //     if let Some(main) = funcs.get("main") {
-        //         assert_eq!(main.args.len(), 0);
-        //         match eval_expr(&main.body, &funcs, &mut Vec::new()) {
-        //             Ok(val) => println!("Return value: {}", val),
-        //             Err(e) => errs.push(Simple::custom(e.span, e.msg)),
-        //         }
-        //     } else {
-        //         panic!("No main function!");
-        //     }
-        // }
-
-        (ast, parse_errs, semantic_tokens)
-    } else {
-        (None, Vec::new(), vec![])
+pub fn _parse(src: &str) -> (Option<HashMap<String, Func>>, Vec<Simple<String>>) {
+    let (tokens, errs) = lexer().parse_recovery(src);
+    let Some(tokens) = tokens else {
+        return (
+            None,
+            errs.into_iter().map(|e| e.map(|c| c.to_string())).collect(),
+        );
     };
-
-    // let parse_errors = errs
-    //     .into_iter()
-    //     .map(|e| e.map(|c| c.to_string()))
-    //     .chain(
-    //         tokenize_errors
-    //             .into_iter()
-    //             .map(|e| e.map(|tok| tok.to_string())),
-    //     )
-    //     .collect::<Vec<_>>();
-
-    dbg!(&ast);
-    dbg!(&semantic_tokens);
-
-    // (ast, parse_errors, semantic_tokens)
-    // (ast, Vec::new(), semantic_tokens)
-    let hm: HashMap<String, Func> = HashMap::new();
-    // (Some(hm), Vec::new(), semantic_tokens)
-    let st = vec![ImCompleteSemanticToken {
-        start: 0,
-        length: 5,
-        token_type: 5,
-    }];
-    (Some(hm), Vec::new(), st)
-
-    // .for_each(|e| {
-    //     let report = match e.reason() {
-    //         chumsky::error::SimpleReason::Unclosed { span, delimiter } => {}
-    //         chumsky::error::SimpleReason::Unexpected => {}
-    //         chumsky::error::SimpleReason::Custom(msg) => {}
-    //     };
-    // });
+    let len = src.chars().count();
+    let (ast, parse_errs) =
+        funcs_parser().parse_recovery(Stream::from_iter(len..len + 1, tokens.into_iter()));
+    let errors = errs
+        .into_iter()
+        .map(|e| e.map(|c| c.to_string()))
+        .chain(parse_errs.into_iter().map(|e| e.map(|tok| tok.to_string())))
+        .collect();
+    (ast, errors)
 }
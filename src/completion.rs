@@ -1,109 +1,398 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
-use crate::chumsky::{Expr, Func, Spanned};
+use ropey::Rope;
+
+use crate::registry::{AttributeSpec, BodyKind, BodyPresence, SectionRegistry, SectionSpec};
+use crate::workspace::WorkspaceIndex;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ImCompleteCompletionItem {
-    Variable(String),
-    Function(String, Vec<String>),
+    // name, docs, snippet
+    Section(String, String, String),
+    // key, docs
+    Attribute(String, String),
+    Value(String),
+    // name, is a directory. Directory names end
+    // with `/` so the next level can be completed.
+    Path(String, bool),
+}
+
+// Where path attributes look for files
+#[derive(Debug, Clone, Default)]
+pub struct PathContext {
+    // The file being edited
+    pub document: PathBuf,
+    pub asset_dirs: Vec<PathBuf>,
 }
-/// return (need_to_continue_search, founded reference)
+
+// `prefix` is the document text up to the cursor.
+// Section names are offered after the `-- ` that
+// starts a line. A `-- ` line right under a section
+// header (or its other attributes) is an attribute
+// so it gets keys and values for that section.
 pub fn completion(
-    ast: &HashMap<String, Func>,
-    ident_offset: usize,
-) -> HashMap<String, ImCompleteCompletionItem> {
-    let mut map = HashMap::new();
-    for (_, v) in ast.iter() {
-        if v.name.1.end < ident_offset {
-            map.insert(
-                v.name.0.clone(),
-                ImCompleteCompletionItem::Function(
-                    v.name.0.clone(),
-                    v.args.clone().into_iter().map(|(name, _)| name).collect(),
-                ),
-            );
-        }
+    prefix: &str,
+    registry: &SectionRegistry,
+    workspace: &WorkspaceIndex,
+    paths: &PathContext,
+) -> Vec<ImCompleteCompletionItem> {
+    let mut lines = prefix.split('\n').rev();
+    let line = lines.next().unwrap_or_default();
+    let Some(partial) = line.strip_prefix("-- ") else {
+        return vec![];
+    };
+    // The lines above, closest first, so the
+    // section header is the last one
+    let above = lines
+        .map(|line| line.trim_end())
+        .take_while(|line| line.starts_with("-- "))
+        .collect::<Vec<_>>();
+    match above.split_last() {
+        None => section_completion(partial, registry),
+        Some((header, siblings)) => match registry.get(header[3..].trim_end_matches('/')) {
+            Some(spec) => attribute_completion(partial, siblings, spec, workspace, paths),
+            None => vec![],
+        },
     }
+}
 
-    // collect params variable
-    for (_, v) in ast.iter() {
-        if v.span.end > ident_offset && v.span.start < ident_offset {
-            // log::debug!("this is completion from body {}", name);
-            v.args.iter().for_each(|(item, _)| {
-                map.insert(
-                    item.clone(),
-                    ImCompleteCompletionItem::Variable(item.clone()),
-                );
-            });
-            get_completion_of(&v.body, &mut map, ident_offset);
-        }
+// The part of the document `completion` reads: the
+// line up to the cursor and the `-- ` lines right
+// above it. The rest of the document isn't copied
+// since this runs on most keystrokes.
+pub fn completion_prefix(rope: &Rope, offset: usize) -> Option<String> {
+    let end = rope.try_byte_to_char(offset).ok()?;
+    let mut line = rope.char_to_line(end);
+    while line > 0 && rope.line(line - 1).chars().take(3).eq("-- ".chars()) {
+        line -= 1;
+    }
+    Some(rope.slice(rope.line_to_char(line)..end).to_string())
+}
+
+fn section_completion(partial: &str, registry: &SectionRegistry) -> Vec<ImCompleteCompletionItem> {
+    if partial.contains([' ', ':', '/']) {
+        return vec![];
     }
-    map
+    registry
+        .sections()
+        .filter(|spec| spec.name.starts_with(partial))
+        .map(|spec| {
+            ImCompleteCompletionItem::Section(
+                spec.name.clone(),
+                spec.docs.clone(),
+                section_snippet(spec),
+            )
+        })
+        .collect()
 }
 
-pub fn get_completion_of(
-    expr: &Spanned<Expr>,
-    definition_map: &mut HashMap<String, ImCompleteCompletionItem>,
-    ident_offset: usize,
-) -> bool {
-    match &expr.0 {
-        Expr::Error => true,
-        Expr::Value(_) => true,
-        // Expr::List(exprs) => exprs
-        //     .iter()
-        //     .for_each(|expr| get_definition(expr, definition_ass_list)),
-        Expr::Local(local) => {
-            !(ident_offset >= local.1.start && ident_offset < local.1.end)
+// `siblings` are the attribute lines already above
+// the cursor so their keys aren't offered again
+fn attribute_completion(
+    partial: &str,
+    siblings: &[&str],
+    spec: &SectionSpec,
+    workspace: &WorkspaceIndex,
+    paths: &PathContext,
+) -> Vec<ImCompleteCompletionItem> {
+    match partial.split_once(':') {
+        None if !partial.contains(' ') => {
+            let used = siblings
+                .iter()
+                .filter_map(|line| line[3..].split(':').next())
+                .map(|key| key.trim())
+                .collect::<Vec<_>>();
+            spec.attributes
+                .iter()
+                .filter(|attr| {
+                    attr.name.starts_with(partial) && !used.contains(&attr.name.as_str())
+                })
+                .map(|attr| {
+                    ImCompleteCompletionItem::Attribute(attr.name.clone(), attr.docs.clone())
+                })
+                .collect()
         }
-        Expr::Let(name, lhs, rest, _name_span) => {
-            definition_map.insert(
-                name.clone(),
-                ImCompleteCompletionItem::Variable(name.clone()),
-            );
-            match get_completion_of(lhs, definition_map, ident_offset) {
-                true => get_completion_of(rest, definition_map, ident_offset),
-                false => false,
+        Some((key, value)) => match spec.attribute(key.trim()) {
+            Some(attr) if attr.enumerated => {
+                let value = value.trim_start();
+                workspace
+                    .attribute_values(&spec.name, &attr.name)
+                    .into_iter()
+                    .filter(|known| known.starts_with(value) && known != value)
+                    .map(ImCompleteCompletionItem::Value)
+                    .collect()
             }
-        }
-        Expr::Then(first, second) => match get_completion_of(first, definition_map, ident_offset) {
-            true => get_completion_of(second, definition_map, ident_offset),
-            false => false,
-        },
-        Expr::Binary(lhs, _op, rhs) => match get_completion_of(lhs, definition_map, ident_offset) {
-            true => get_completion_of(rhs, definition_map, ident_offset),
-            false => false,
+            Some(attr) if attr.path => path_completion(value.trim_start(), attr, paths),
+            _ => vec![],
         },
-        Expr::Call(callee, args) => {
-            match get_completion_of(callee, definition_map, ident_offset) {
-                true => {}
-                false => return false,
-            }
-            for expr in &args.0 {
-                match get_completion_of(expr, definition_map, ident_offset) {
-                    true => continue,
-                    false => return false,
-                }
-            }
-            true
+        None => vec![],
+    }
+}
+
+// Lists one directory at a time. `/images/ca` looks
+// for entries starting with `ca` in `images` under
+// each asset directory. Paths without a leading `/`
+// are tried next to the document too.
+fn path_completion(
+    value: &str,
+    attr: &AttributeSpec,
+    paths: &PathContext,
+) -> Vec<ImCompleteCompletionItem> {
+    let (dir, partial) = match value.rfind('/') {
+        Some(i) => (&value[..=i], &value[i + 1..]),
+        None => ("", value),
+    };
+    let mut bases = vec![];
+    match dir.strip_prefix('/') {
+        Some(rest) => bases.extend(paths.asset_dirs.iter().map(|base| base.join(rest))),
+        None => {
+            bases.extend(paths.document.parent().map(|base| base.join(dir)));
+            bases.extend(paths.asset_dirs.iter().map(|base| base.join(dir)));
         }
-        Expr::If(test, consequent, alternative) => {
-            match get_completion_of(test, definition_map, ident_offset) {
-                true => {}
-                false => return false,
-            }
-            match get_completion_of(consequent, definition_map, ident_offset) {
-                true => {}
-                false => return false,
+    }
+    // Name to whether it's a directory. The same name
+    // can turn up in more than one base.
+    let mut entries = BTreeMap::new();
+    bases.iter().for_each(|base| {
+        let Ok(read_dir) = std::fs::read_dir(base) else {
+            return;
+        };
+        read_dir.flatten().for_each(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') || !name.starts_with(partial) {
+                return;
             }
-            get_completion_of(alternative, definition_map, ident_offset)
-        }
-        Expr::Print(expr) => get_completion_of(expr, definition_map, ident_offset),
-        Expr::List(lst) => {
-            for expr in lst {
-                match get_completion_of(expr, definition_map, ident_offset) {
-                    true => continue,
-                    false => return false,
-                }
+            let is_dir = entry.path().is_dir();
+            if is_dir || has_extension(&entry.path(), &attr.extensions) {
+                entries.insert(name, is_dir);
             }
-            true
-        }
+        });
+    });
+    entries
+        .into_iter()
+        .map(|(name, is_dir)| match is_dir {
+            true => ImCompleteCompletionItem::Path(format!("{}/", name), true),
+            false => ImCompleteCompletionItem::Path(name, false),
+        })
+        .collect()
+}
+
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    extensions.is_empty()
+        || path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .is_some_and(|ext| extensions.contains(&ext))
+}
+
+// The section name plus the blank line it has to
+// be followed by and a placeholder for the body
+pub fn section_snippet(spec: &SectionSpec) -> String {
+    // Optional bodies are left for the user to add
+    if spec.body != BodyPresence::Required {
+        return spec.name.clone();
+    }
+    match spec.kind {
+        BodyKind::Paragraph => format!("{}\n\n${{1:text}}", spec.name),
+        BodyKind::List => format!("{}\n\n- ${{1:item}}", spec.name),
+        BodyKind::Raw => format!("{}\n\n${{1}}", spec.name),
+        BodyKind::Metadata => format!("{}\n-- ${{1:key}}: ${{2:value}}", spec.name),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn names(items: Vec<ImCompleteCompletionItem>) -> Vec<String> {
+        items
+            .into_iter()
+            .map(|item| match item {
+                ImCompleteCompletionItem::Section(name, _, _) => name,
+                ImCompleteCompletionItem::Attribute(key, _) => key,
+                ImCompleteCompletionItem::Value(value) => value,
+                ImCompleteCompletionItem::Path(name, _) => name,
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn test_completion_prefix() {
+        let rope = Rope::from_str("-- p\n\nAlfa\n\n-- image\n-- src: bravo.jpg\n-- al");
+        assert_eq!(
+            Some("-- image\n-- src: bravo.jpg\n-- al".to_string()),
+            completion_prefix(&rope, rope.len_bytes())
+        );
+        assert_eq!(Some("Al".to_string()), completion_prefix(&rope, 8));
+        assert_eq!(None, completion_prefix(&rope, 100));
+    }
+
+    #[test]
+    pub fn test_section_name_completion() {
+        let registry = SectionRegistry::default();
+        let workspace = WorkspaceIndex::default();
+        let paths = PathContext::default();
+        let left = vec![ImCompleteCompletionItem::Section(
+            "notes".to_string(),
+            registry.get("notes").unwrap().docs.clone(),
+            "notes\n\n- ${1:item}".to_string(),
+        )];
+        assert_eq!(left, completion("-- notes", &registry, &workspace, &paths));
+        assert_eq!(
+            "hr".to_string(),
+            section_snippet(registry.get("hr").unwrap())
+        );
+        assert_eq!(
+            7,
+            completion("alfa\n\n-- h", &registry, &workspace, &paths).len()
+        );
+        assert_eq!(
+            registry.sections().count(),
+            completion("-- ", &registry, &workspace, &paths).len()
+        );
+    }
+
+    #[test]
+    pub fn test_no_completion_outside_section_header() {
+        let registry = SectionRegistry::default();
+        let workspace = WorkspaceIndex::default();
+        let paths = PathContext::default();
+        assert_eq!(
+            Vec::<ImCompleteCompletionItem>::new(),
+            completion("alfa -- ", &registry, &workspace, &paths)
+        );
+        assert_eq!(
+            Vec::<ImCompleteCompletionItem>::new(),
+            completion("-- title ", &registry, &workspace, &paths)
+        );
+        assert_eq!(
+            Vec::<ImCompleteCompletionItem>::new(),
+            completion("--", &registry, &workspace, &paths)
+        );
+    }
+
+    #[test]
+    pub fn test_attribute_key_completion() {
+        let registry = SectionRegistry::default();
+        let workspace = WorkspaceIndex::default();
+        let paths = PathContext::default();
+        assert_eq!(
+            vec!["id", "class", "alt", "src"],
+            names(completion("-- image\n-- ", &registry, &workspace, &paths))
+        );
+        assert_eq!(
+            vec!["date", "site", "status", "template", "updated"],
+            names(completion(
+                "-- metadata\n-- id: alfa\n-- ",
+                &registry,
+                &workspace,
+                &paths
+            ))
+        );
+        assert_eq!(
+            vec!["site", "status"],
+            names(completion(
+                "-- metadata\n-- s",
+                &registry,
+                &workspace,
+                &paths
+            ))
+        );
+    }
+
+    #[test]
+    pub fn test_attribute_value_completion() {
+        let registry = SectionRegistry::default();
+        let workspace = WorkspaceIndex::default();
+        let paths = PathContext::default();
+        workspace.insert(
+            PathBuf::from("/site/alfa.neo"),
+            "-- metadata\n-- status: published\n-- template: post",
+            &registry,
+        );
+        workspace.insert(
+            PathBuf::from("/site/bravo.neo"),
+            "-- metadata\n-- status: draft\n-- site: bravo",
+            &registry,
+        );
+        assert_eq!(
+            vec!["draft", "published"],
+            names(completion(
+                "-- metadata\n-- status: ",
+                &registry,
+                &workspace,
+                &paths
+            ))
+        );
+        assert_eq!(
+            vec!["post"],
+            names(completion(
+                "-- metadata\n-- template: p",
+                &registry,
+                &workspace,
+                &paths
+            ))
+        );
+        assert_eq!(
+            Vec::<String>::new(),
+            names(completion(
+                "-- metadata\n-- site: ",
+                &registry,
+                &workspace,
+                &paths
+            ))
+        );
+    }
+
+    #[test]
+    pub fn test_path_completion() {
+        let registry = SectionRegistry::default();
+        let workspace = WorkspaceIndex::default();
+        let root =
+            std::env::temp_dir().join(format!("neopolitan-path-completion-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("static/images/2023")).unwrap();
+        std::fs::create_dir_all(root.join("posts")).unwrap();
+        [
+            "static/images/alfa.jpg",
+            "static/images/bravo.txt",
+            "posts/charlie.png",
+        ]
+        .iter()
+        .for_each(|file| std::fs::write(root.join(file), "").unwrap());
+        let paths = PathContext {
+            document: root.join("posts/delta.neo"),
+            asset_dirs: vec![root.join("static")],
+        };
+        assert_eq!(
+            vec!["images/"],
+            names(completion(
+                "-- image\n-- src: /",
+                &registry,
+                &workspace,
+                &paths
+            ))
+        );
+        assert_eq!(
+            vec!["2023/", "alfa.jpg"],
+            names(completion(
+                "-- image\n-- src: /images/",
+                &registry,
+                &workspace,
+                &paths
+            ))
+        );
+        assert_eq!(
+            vec!["charlie.png", "images/"],
+            names(completion(
+                "-- image\n-- src: ",
+                &registry,
+                &workspace,
+                &paths
+            ))
+        );
+        std::fs::remove_dir_all(&root).unwrap();
     }
 }
@@ -0,0 +1,39 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::metadata::MetadataSchema;
+use crate::registry::SectionSpec;
+
+// Workspace settings read from `neopolitan.json`
+// at the root of each workspace folder.
+
+pub const CONFIG_FILE_NAME: &str = "neopolitan.json";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub sections: Vec<SectionSpec>,
+    pub metadata: Option<MetadataSchema>,
+    // Directories path completion looks in, relative
+    // to the workspace root. The root itself is used
+    // when there aren't any.
+    #[serde(default)]
+    pub assets: Vec<String>,
+}
+
+impl Config {
+    // Returns `Ok(None)` when there's no config file
+    // so a missing file isn't treated as an error
+    pub fn load(root: &Path) -> Result<Option<Config>, String> {
+        let path = root.join(CONFIG_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| format!("could not parse {}: {}", path.display(), e))
+    }
+}
@@ -0,0 +1,122 @@
+use std::ops::Range;
+
+use tower_lsp::lsp_types::DiagnosticSeverity;
+
+use crate::neo_ast::{Document, ErrorKind, ErrorNode};
+
+#[derive(Debug, PartialEq)]
+pub struct ImCompleteDiagnostic {
+    pub span: Range<usize>,
+    pub message: String,
+    pub severity: DiagnosticSeverity,
+}
+
+pub fn diagnostics_from_document(document: &Document) -> Vec<ImCompleteDiagnostic> {
+    document
+        .errors
+        .iter()
+        .map(diagnostic_from_error)
+        .collect::<Vec<_>>()
+}
+
+pub fn diagnostic_from_error(error: &ErrorNode) -> ImCompleteDiagnostic {
+    let (span, message) = match &error.kind {
+        ErrorKind::UnknownSection { name, name_span } => (
+            name_span.clone(),
+            format!("unknown section type `-- {}`", name),
+        ),
+        ErrorKind::MissingBlankLine { name, name_span } => (
+            error.span.start..name_span.end,
+            format!("section `-- {}` must be followed by a blank line", name),
+        ),
+        ErrorKind::InvalidSection { name, name_span } => (
+            error.span.start..name_span.end,
+            format!("section `-- {}` could not be parsed", name),
+        ),
+        ErrorKind::UnclosedBlock { name, .. } => (
+            error.span.clone(),
+            format!("block `-- {}/` is never closed with `-- /{}`", name, name),
+        ),
+        ErrorKind::MismatchedBlockEnd {
+            expected, found, ..
+        } => (
+            error.span.clone(),
+            format!("expected `-- /{}` but found `-- /{}`", expected, found),
+        ),
+        ErrorKind::UnexpectedBlockEnd { name, .. } => (
+            error.span.clone(),
+            format!("`-- /{}` doesn't close an open block", name),
+        ),
+        ErrorKind::UnclosedInlineTag { open } => (
+            open.clone(),
+            "inline tag `<<` is never closed with `>>`".to_string(),
+        ),
+        ErrorKind::InvalidAttribute { key, key_span } => (
+            key_span.clone(),
+            format!("attribute key `{}` can't contain whitespace", key),
+        ),
+        ErrorKind::UnexpectedContent => (
+            error.span.clone(),
+            "content must be inside a section".to_string(),
+        ),
+    };
+    ImCompleteDiagnostic {
+        span,
+        message,
+        severity: DiagnosticSeverity::ERROR,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nom_parser::nom_parse;
+    use crate::registry::SectionRegistry;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    pub fn test_unknown_section_diagnostic() {
+        let source = "-- titel\n\nAlfa";
+        let document = nom_parse(source, &SectionRegistry::default()).unwrap().1;
+        let left = vec![ImCompleteDiagnostic {
+            span: 3..8,
+            message: "unknown section type `-- titel`".to_string(),
+            severity: DiagnosticSeverity::ERROR,
+        }];
+        assert_eq!(left, diagnostics_from_document(&document));
+    }
+
+    #[test]
+    pub fn test_missing_blank_line_diagnostic() {
+        let source = "-- title\n\nAlfa\n\n-- h2\nBravo";
+        let document = nom_parse(source, &SectionRegistry::default()).unwrap().1;
+        let left = vec![ImCompleteDiagnostic {
+            span: 16..21,
+            message: "section `-- h2` must be followed by a blank line".to_string(),
+            severity: DiagnosticSeverity::ERROR,
+        }];
+        assert_eq!(left, diagnostics_from_document(&document));
+    }
+
+    #[test]
+    pub fn test_no_diagnostics_for_valid_document() {
+        let source = "-- title\n\nAlfa\n\n-- h2\n\nBravo";
+        let document = nom_parse(source, &SectionRegistry::default()).unwrap().1;
+        assert_eq!(
+            Vec::<ImCompleteDiagnostic>::new(),
+            diagnostics_from_document(&document)
+        );
+    }
+
+    #[test]
+    pub fn test_unclosed_block_diagnostic() {
+        let source = "-- div/\n\n-- p\n\nAlfa";
+        let document = nom_parse(source, &SectionRegistry::default()).unwrap().1;
+        let left = vec![ImCompleteDiagnostic {
+            span: 0..7,
+            message: "block `-- div/` is never closed with `-- /div`".to_string(),
+            severity: DiagnosticSeverity::ERROR,
+        }];
+        assert_eq!(left, diagnostics_from_document(&document));
+    }
+}
@@ -0,0 +1,259 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types::DiagnosticSeverity;
+
+use crate::diagnostic::ImCompleteDiagnostic;
+use crate::hover::video_url;
+use crate::jump_definition::is_external;
+use crate::neo_ast::{Document, Inline};
+use crate::workspace::{normalize_path, WorkspaceIndex};
+
+// Sections whose `src` or `url` attribute is a link
+pub const LINK_SECTIONS: &[&str] = &["bookmark", "image", "vimeo", "youtube"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImCompleteLinkTarget {
+    Url(String),
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImCompleteDocumentLink {
+    pub span: Range<usize>,
+    pub target: ImCompleteLinkTarget,
+}
+
+// Whatever in the document points somewhere else
+enum Candidate {
+    Url(String),
+    // A `<<link|...>>` target which can be an id or
+    // a path
+    Link(String),
+    // An attribute value which is always a path
+    File(String),
+}
+
+pub fn document_links(
+    document: &Document,
+    path: &Path,
+    roots: &[PathBuf],
+    index: &WorkspaceIndex,
+) -> Vec<ImCompleteDocumentLink> {
+    resolve_links(document, path, roots, index)
+        .into_iter()
+        .filter_map(|(span, _, target)| {
+            Some(ImCompleteDocumentLink {
+                span,
+                target: target?,
+            })
+        })
+        .collect()
+}
+
+// Local files that can't be found, either on disk
+// or as an unsaved page in the workspace
+pub fn missing_file_diagnostics(
+    document: &Document,
+    path: &Path,
+    roots: &[PathBuf],
+    index: &WorkspaceIndex,
+) -> Vec<ImCompleteDiagnostic> {
+    resolve_links(document, path, roots, index)
+        .into_iter()
+        .filter(|(_, _, target)| target.is_none())
+        .map(|(span, text, _)| ImCompleteDiagnostic {
+            span,
+            message: format!("`{}` doesn't exist", text),
+            severity: DiagnosticSeverity::WARNING,
+        })
+        .collect()
+}
+
+// `None` means the target is a local file that
+// doesn't exist. Things that aren't links at all
+// (like unknown ids) are left out.
+fn resolve_links(
+    document: &Document,
+    path: &Path,
+    roots: &[PathBuf],
+    index: &WorkspaceIndex,
+) -> Vec<(Range<usize>, String, Option<ImCompleteLinkTarget>)> {
+    let mut response = candidates(document)
+        .into_iter()
+        .filter_map(|(span, candidate)| {
+            let (text, is_path) = match candidate {
+                Candidate::Url(url) => {
+                    return Some((span, url.clone(), Some(ImCompleteLinkTarget::Url(url))))
+                }
+                Candidate::Link(text) => (text, false),
+                Candidate::File(text) => (text, true),
+            };
+            if is_external(&text) {
+                return Some((span, text.clone(), Some(ImCompleteLinkTarget::Url(text))));
+            }
+            if text.is_empty() || text.starts_with('#') {
+                return None;
+            }
+            if !is_path {
+                if let Some((page, _)) = index.pages_with_id(&text).into_iter().next() {
+                    return Some((span, text, Some(ImCompleteLinkTarget::File(page))));
+                }
+                if !text.contains(['/', '.']) {
+                    return None;
+                }
+            }
+            let target = resolve_file(&text, path, roots, index).map(ImCompleteLinkTarget::File);
+            Some((span, text, target))
+        })
+        .collect::<Vec<_>>();
+    response.sort_by_key(|(span, _, _)| span.start);
+    response
+}
+
+fn candidates(document: &Document) -> Vec<(Range<usize>, Candidate)> {
+    let mut response = vec![];
+    document.all_sections().into_iter().for_each(|section| {
+        if LINK_SECTIONS.contains(&section.name.as_str()) {
+            section
+                .attributes
+                .iter()
+                .filter(|attr| attr.key == "src" || attr.key == "url")
+                .filter_map(|attr| attr.value.as_ref())
+                .for_each(|value| {
+                    response.push((value.span.clone(), Candidate::File(value.text.clone())))
+                });
+        }
+        section.paragraphs().into_iter().for_each(|paragraph| {
+            if let Some(url) = video_url(&section.name, &paragraph.text()) {
+                response.push((paragraph.span.clone(), Candidate::Url(url)));
+                return;
+            }
+            paragraph.content.iter().for_each(|item| {
+                if let Inline::Word(word) = item {
+                    let url = word
+                        .text
+                        .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '"', '\'']);
+                    if url.starts_with("https://") || url.starts_with("http://") {
+                        let span = word.span.start..word.span.start + url.len();
+                        response.push((span, Candidate::Url(url.to_string())));
+                    }
+                }
+            });
+        });
+    });
+    document.inline_tags().into_iter().for_each(|tag| {
+        if let Some(target) = tag.link_target() {
+            response.push((target.span.clone(), Candidate::Link(target.text())));
+        }
+    });
+    response
+}
+
+// Paths starting with `/` are relative to the site,
+// so they're only looked up in each workspace root
+// and never on the filesystem root. Other
+// paths try the document's directory first and then
+// the roots.
+pub fn resolve_file(
+    target: &str,
+    from: &Path,
+    roots: &[PathBuf],
+    index: &WorkspaceIndex,
+) -> Option<PathBuf> {
+    let target = target.split(['#', '?']).next().unwrap_or_default();
+    let mut candidates = vec![];
+    match target.strip_prefix('/') {
+        Some(rest) => candidates.extend(roots.iter().map(|root| root.join(rest))),
+        None => {
+            candidates.extend(from.parent().map(|dir| dir.join(target)));
+            candidates.extend(roots.iter().map(|root| root.join(target)));
+        }
+    }
+    candidates
+        .into_iter()
+        .map(|candidate| normalize_path(&candidate))
+        .find(|candidate| index.get(candidate).is_some() || index.file_exists(candidate))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nom_parser::nom_parse;
+    use crate::registry::SectionRegistry;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    pub fn test_document_links() {
+        let registry = SectionRegistry::default();
+        let index = WorkspaceIndex::default();
+        index.insert(
+            PathBuf::from("/site/bravo.neo"),
+            "-- metadata\n-- id: bravo",
+            &registry,
+        );
+        let roots = vec![PathBuf::from(env!("CARGO_MANIFEST_DIR"))];
+        let root = &roots[0];
+        let source = "-- image\n-- src: /README.neo\n\nalfa\n\n-- p\n\nsee https://example.com/alfa. and <<link|bravo|bravo>>\n\n-- youtube\n\ndQw4w9WgXcQ";
+        let document = nom_parse(source, &registry).unwrap().1;
+        let left = vec![
+            ImCompleteDocumentLink {
+                span: 17..28,
+                target: ImCompleteLinkTarget::File(root.join("README.neo")),
+            },
+            ImCompleteDocumentLink {
+                span: 46..70,
+                target: ImCompleteLinkTarget::Url("https://example.com/alfa".to_string()),
+            },
+            ImCompleteDocumentLink {
+                span: 89..94,
+                target: ImCompleteLinkTarget::File(PathBuf::from("/site/bravo.neo")),
+            },
+            ImCompleteDocumentLink {
+                span: 110..121,
+                target: ImCompleteLinkTarget::Url(
+                    "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+                ),
+            },
+        ];
+        assert_eq!(
+            left,
+            document_links(&document, &root.join("alfa.neo"), &roots, &index)
+        );
+    }
+
+    #[test]
+    pub fn test_missing_file_diagnostics() {
+        let registry = SectionRegistry::default();
+        let index = WorkspaceIndex::default();
+        index.insert(PathBuf::from("/site/posts/bravo.neo"), "", &registry);
+        let source = "-- image\n-- src: charlie.jpg\n\nalfa\n\n-- p\n\n<<link|bravo|posts/bravo.neo>> <<link|delta|delta>>";
+        let document = nom_parse(source, &registry).unwrap().1;
+        let left = vec![ImCompleteDiagnostic {
+            span: 17..28,
+            message: "`charlie.jpg` doesn't exist".to_string(),
+            severity: DiagnosticSeverity::WARNING,
+        }];
+        assert_eq!(
+            left,
+            missing_file_diagnostics(
+                &document,
+                Path::new("/site/drafts/alfa.neo"),
+                &[PathBuf::from("/site")],
+                &index
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_site_paths_stay_in_the_roots() {
+        let index = WorkspaceIndex::default();
+        let roots = vec![PathBuf::from(env!("CARGO_MANIFEST_DIR"))];
+        let from = roots[0].join("alfa.neo");
+        assert_eq!(
+            Some(roots[0].join("README.neo")),
+            resolve_file("/README.neo", &from, &roots, &index)
+        );
+        assert_eq!(None, resolve_file("/etc/passwd", &from, &roots, &index));
+    }
+}
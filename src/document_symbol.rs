@@ -0,0 +1,204 @@
+use std::ops::Range;
+
+use tower_lsp::lsp_types::SymbolKind;
+
+use crate::neo_ast::{Document, Section, SectionBody};
+use crate::registry::{BodyKind, SectionRegistry};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImCompleteSymbol {
+    pub name: String,
+    pub detail: Option<String>,
+    pub kind: SymbolKind,
+    pub span: Range<usize>,
+    pub selection_span: Range<usize>,
+    pub children: Vec<ImCompleteSymbol>,
+}
+
+// The outline of a document. `-- title` is the root,
+// headings nest under each other by level and every
+// other section goes under the heading before it.
+pub fn document_symbols(document: &Document, registry: &SectionRegistry) -> Vec<ImCompleteSymbol> {
+    let mut stack: Vec<(usize, ImCompleteSymbol)> = vec![];
+    let mut response = vec![];
+    document.sections.iter().for_each(|section| {
+        let level = if section.name == "title" {
+            Some(0)
+        } else {
+            heading_level(&section.name)
+        };
+        let symbol = section_symbol(section, registry);
+        match level {
+            Some(level) => {
+                while stack.last().is_some_and(|(top, _)| *top >= level) {
+                    close_symbol(&mut stack, &mut response);
+                }
+                stack.push((level, symbol));
+            }
+            None => match stack.last_mut() {
+                Some((_, parent)) => parent.children.push(symbol),
+                None => response.push(symbol),
+            },
+        }
+    });
+    while !stack.is_empty() {
+        close_symbol(&mut stack, &mut response);
+    }
+    response.iter_mut().for_each(extend_span);
+    response
+}
+
+// `h1` through `h6` are headings. Everything else
+// (including `title`) isn't.
+pub fn heading_level(name: &str) -> Option<usize> {
+    let level = name.strip_prefix('h')?.parse::<usize>().ok()?;
+    (1..=6).contains(&level).then_some(level)
+}
+
+fn close_symbol(stack: &mut Vec<(usize, ImCompleteSymbol)>, response: &mut Vec<ImCompleteSymbol>) {
+    if let Some((_, symbol)) = stack.pop() {
+        match stack.last_mut() {
+            Some((_, parent)) => parent.children.push(symbol),
+            None => response.push(symbol),
+        }
+    }
+}
+
+fn section_symbol(section: &Section, registry: &SectionRegistry) -> ImCompleteSymbol {
+    let text = section
+        .paragraphs()
+        .first()
+        .map(|p| (p.text(), p.span.clone()));
+    let is_heading = section.name == "title" || heading_level(&section.name).is_some();
+    let (name, selection_span, detail) = match text {
+        Some((text, span)) if is_heading && !text.is_empty() => {
+            (text, span, Some(section.name.clone()))
+        }
+        _ => (section.name.clone(), section.name_span.clone(), None),
+    };
+    let kind = if section.name == "title" {
+        SymbolKind::FILE
+    } else if is_heading {
+        SymbolKind::NAMESPACE
+    } else {
+        match registry.get(&section.name).map(|spec| spec.kind) {
+            Some(BodyKind::List) => SymbolKind::ARRAY,
+            Some(BodyKind::Raw) => SymbolKind::STRING,
+            Some(BodyKind::Metadata) => SymbolKind::OBJECT,
+            _ if section.block.is_some() => SymbolKind::STRUCT,
+            _ => SymbolKind::FIELD,
+        }
+    };
+    let mut children = section
+        .children()
+        .iter()
+        .map(|child| section_symbol(child, registry))
+        .collect::<Vec<_>>();
+    // Metadata values show up under the section so
+    // things like the page id are in the outline
+    if section.body == SectionBody::Empty && kind == SymbolKind::OBJECT {
+        children.extend(section.attributes.iter().map(|attr| ImCompleteSymbol {
+            name: attr.key.clone(),
+            detail: attr.value.as_ref().map(|value| value.text.clone()),
+            kind: SymbolKind::PROPERTY,
+            span: attr.span.clone(),
+            selection_span: attr.key_span.clone(),
+            children: vec![],
+        }));
+    }
+    ImCompleteSymbol {
+        name,
+        detail,
+        kind,
+        span: section.span.clone(),
+        selection_span,
+        children,
+    }
+}
+
+// A parent's range has to cover its children
+fn extend_span(symbol: &mut ImCompleteSymbol) {
+    symbol.children.iter_mut().for_each(extend_span);
+    if let Some(end) = symbol.children.iter().map(|c| c.span.end).max() {
+        symbol.span.end = symbol.span.end.max(end);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nom_parser::nom_parse;
+    use pretty_assertions::assert_eq;
+
+    fn outline(symbols: &[ImCompleteSymbol]) -> Vec<(String, Vec<String>)> {
+        symbols
+            .iter()
+            .map(|s| {
+                (
+                    s.name.clone(),
+                    s.children.iter().map(|c| c.name.clone()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn test_headings_nest_under_title() {
+        let source = "-- title\n\nAlfa Bravo\n\n-- h2\n\nCharlie\n\n-- code\n\nx = 1\n\n-- h3\n\nDelta\n\n-- h2\n\nEcho";
+        let registry = SectionRegistry::default();
+        let document = nom_parse(source, &registry).unwrap().1;
+        let symbols = document_symbols(&document, &registry);
+        assert_eq!(1, symbols.len());
+        let title = &symbols[0];
+        assert_eq!("Alfa Bravo".to_string(), title.name);
+        assert_eq!(SymbolKind::FILE, title.kind);
+        assert_eq!(0..source.len(), title.span);
+        assert_eq!(10..20, title.selection_span);
+        assert_eq!(
+            vec![
+                (
+                    "Charlie".to_string(),
+                    vec!["code".to_string(), "Delta".to_string()]
+                ),
+                ("Echo".to_string(), vec![]),
+            ],
+            outline(&title.children)
+        );
+        assert_eq!(SymbolKind::STRING, title.children[0].children[0].kind);
+        assert_eq!(SymbolKind::NAMESPACE, title.children[0].children[1].kind);
+    }
+
+    #[test]
+    pub fn test_metadata_symbol() {
+        let source = "-- metadata\n-- id: alfa\n-- status: draft";
+        let registry = SectionRegistry::default();
+        let document = nom_parse(source, &registry).unwrap().1;
+        let symbols = document_symbols(&document, &registry);
+        let left = ImCompleteSymbol {
+            name: "metadata".to_string(),
+            detail: None,
+            kind: SymbolKind::OBJECT,
+            span: 0..40,
+            selection_span: 3..11,
+            children: vec![
+                ImCompleteSymbol {
+                    name: "id".to_string(),
+                    detail: Some("alfa".to_string()),
+                    kind: SymbolKind::PROPERTY,
+                    span: 12..23,
+                    selection_span: 15..17,
+                    children: vec![],
+                },
+                ImCompleteSymbol {
+                    name: "status".to_string(),
+                    detail: Some("draft".to_string()),
+                    kind: SymbolKind::PROPERTY,
+                    span: 24..40,
+                    selection_span: 27..33,
+                    children: vec![],
+                },
+            ],
+        };
+        assert_eq!(vec![left], symbols);
+    }
+}
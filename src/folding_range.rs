@@ -0,0 +1,80 @@
+use std::ops::Range;
+
+use tower_lsp::lsp_types::FoldingRangeKind;
+
+use crate::neo_ast::{Document, Section, SectionBody};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImCompleteFoldingRange {
+    pub span: Range<usize>,
+    pub kind: Option<FoldingRangeKind>,
+}
+
+// One range for every section, plus its attributes
+// and raw body. Ranges that end up on a single line
+// are dropped when they're turned into positions.
+pub fn folding_ranges(document: &Document) -> Vec<ImCompleteFoldingRange> {
+    let mut ranges = vec![];
+    document
+        .sections
+        .iter()
+        .for_each(|section| folding_ranges_from_section(section, &mut ranges));
+    ranges
+}
+
+fn folding_ranges_from_section(section: &Section, ranges: &mut Vec<ImCompleteFoldingRange>) {
+    // Blocks already run to their end marker
+    ranges.push(ImCompleteFoldingRange {
+        span: section.span.clone(),
+        kind: section.block.as_ref().map(|_| FoldingRangeKind::Region),
+    });
+    if let (Some(first), Some(last)) = (section.attributes.first(), section.attributes.last()) {
+        ranges.push(ImCompleteFoldingRange {
+            span: first.span.start..last.span.end,
+            kind: None,
+        });
+    }
+    if let SectionBody::Raw(raw) = &section.body {
+        ranges.push(ImCompleteFoldingRange {
+            span: raw.span.clone(),
+            kind: None,
+        });
+    }
+    section
+        .children()
+        .iter()
+        .for_each(|child| folding_ranges_from_section(child, ranges));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nom_parser::nom_parse;
+    use crate::registry::SectionRegistry;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    pub fn test_folding_ranges() {
+        let source = "-- div/\n-- class: alfa\n-- id: bravo\n\n-- code\n\nx = 1\ny = 2\n\n-- /div";
+        let document = nom_parse(source, &SectionRegistry::default()).unwrap().1;
+        let left = vec![
+            ImCompleteFoldingRange {
+                span: 0..66,
+                kind: Some(FoldingRangeKind::Region),
+            },
+            ImCompleteFoldingRange {
+                span: 8..35,
+                kind: None,
+            },
+            ImCompleteFoldingRange {
+                span: 37..57,
+                kind: None,
+            },
+            ImCompleteFoldingRange {
+                span: 46..57,
+                kind: None,
+            },
+        ];
+        assert_eq!(left, folding_ranges(&document));
+    }
+}
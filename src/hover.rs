@@ -0,0 +1,159 @@
+use std::ops::Range;
+
+use crate::neo_ast::{Document, Section};
+use crate::registry::{AttributeSpec, SectionRegistry, SectionSpec};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImCompleteHover {
+    pub span: Range<usize>,
+    pub contents: String,
+}
+
+// Markdown for whatever is under the cursor. Section
+// names and end markers get the section docs,
+// attribute keys get the attribute docs and video
+// ids get the URL they point to.
+pub fn hover(
+    document: &Document,
+    offset: usize,
+    registry: &SectionRegistry,
+) -> Option<ImCompleteHover> {
+    // Nested sections come after their parents so the
+    // last match is the innermost one
+    let section = document
+        .all_sections()
+        .into_iter()
+        .rev()
+        .find(|section| contains(&section.span, offset))?;
+    let spec = registry.get(&section.name)?;
+    let end_name = section
+        .block
+        .as_ref()
+        .and_then(|block| block.end.as_ref())
+        .map(|end| end.name_span.clone());
+    if let Some(span) = [Some(section.name_span.clone()), end_name]
+        .into_iter()
+        .flatten()
+        .find(|span| contains(span, offset))
+    {
+        return Some(ImCompleteHover {
+            span,
+            contents: section_docs(spec),
+        });
+    }
+    if let Some(attr) = section
+        .attributes
+        .iter()
+        .find(|attr| contains(&attr.key_span, offset))
+    {
+        return Some(ImCompleteHover {
+            span: attr.key_span.clone(),
+            contents: attribute_docs(&attr.key, spec.attribute(&attr.key))?,
+        });
+    }
+    video_hover(section, offset)
+}
+
+fn contains(span: &Range<usize>, offset: usize) -> bool {
+    span.start <= offset && offset <= span.end
+}
+
+pub fn section_docs(spec: &SectionSpec) -> String {
+    let mut response = format!("```neo\n-- {}\n```\n\n{}", spec.name, spec.docs);
+    let required = spec
+        .attributes
+        .iter()
+        .filter(|attr| attr.required)
+        .map(|attr| format!("`{}`", attr.name))
+        .collect::<Vec<_>>();
+    if !required.is_empty() {
+        response.push_str(&format!("\n\nRequired attributes: {}", required.join(", ")));
+    }
+    if !spec.attributes.is_empty() {
+        response.push_str("\n\nAttributes:\n");
+        spec.attributes.iter().for_each(|attr| {
+            response.push_str(&format!("\n- `{}` {}", attr.name, attr.docs));
+        });
+    }
+    if !spec.example.is_empty() {
+        response.push_str(&format!("\n\nExample:\n\n```neo\n{}\n```", spec.example));
+    }
+    response
+}
+
+// Keys that aren't in the registry don't get a hover
+fn attribute_docs(key: &str, spec: Option<&AttributeSpec>) -> Option<String> {
+    let spec = spec?;
+    let required = if spec.required { " (required)" } else { "" };
+    Some(format!(
+        "```neo\n-- {}:\n```\n\n{}{}",
+        key, spec.docs, required
+    ))
+}
+
+pub fn video_url(section: &str, id: &str) -> Option<String> {
+    match section {
+        "youtube" => Some(format!("https://www.youtube.com/watch?v={}", id)),
+        "vimeo" => Some(format!("https://vimeo.com/{}", id)),
+        _ => None,
+    }
+}
+
+fn video_hover(section: &Section, offset: usize) -> Option<ImCompleteHover> {
+    let paragraph = section
+        .paragraphs()
+        .into_iter()
+        .find(|paragraph| contains(&paragraph.span, offset))?;
+    let url = video_url(&section.name, &paragraph.text())?;
+    Some(ImCompleteHover {
+        span: paragraph.span.clone(),
+        contents: format!("[{}]({})", url, url),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nom_parser::nom_parse;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    pub fn test_section_hover() {
+        let registry = SectionRegistry::default();
+        let source = "-- image\n-- src: /alfa.jpg\n\nBravo";
+        let document = nom_parse(source, &registry).unwrap().1;
+        let right = hover(&document, 4, &registry).unwrap();
+        assert_eq!(3..8, right.span);
+        assert!(right.contents.starts_with("```neo\n-- image\n```"));
+        assert!(right.contents.contains("Required attributes: `src`"));
+        assert!(right.contents.contains("Example:"));
+        let right = hover(&document, 13, &registry).unwrap();
+        assert_eq!(12..15, right.span);
+        assert!(right
+            .contents
+            .ends_with("Path to the image file. (required)"));
+        assert_eq!(None, hover(&document, 30, &registry));
+    }
+
+    #[test]
+    pub fn test_block_end_hover() {
+        let registry = SectionRegistry::default();
+        let source = "-- div/\n\n-- p\n\nalfa\n\n-- /div";
+        let document = nom_parse(source, &registry).unwrap().1;
+        let right = hover(&document, 26, &registry).unwrap();
+        assert_eq!(25..28, right.span);
+        assert!(right.contents.starts_with("```neo\n-- div\n```"));
+    }
+
+    #[test]
+    pub fn test_video_hover() {
+        let registry = SectionRegistry::default();
+        let source = "-- youtube\n\ndQw4w9WgXcQ";
+        let document = nom_parse(source, &registry).unwrap().1;
+        let left = ImCompleteHover {
+            span: 12..23,
+            contents: "[https://www.youtube.com/watch?v=dQw4w9WgXcQ](https://www.youtube.com/watch?v=dQw4w9WgXcQ)".to_string(),
+        };
+        assert_eq!(Some(left), hover(&document, 15, &registry));
+    }
+}
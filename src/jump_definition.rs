@@ -1,139 +1,171 @@
-use std::collections::HashMap;
-
-use im_rc::Vector;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
+use crate::document_symbol::heading_level;
+use crate::neo_ast::{Document, InlineTag, Section};
+use crate::workspace::{normalize_path, WorkspaceIndex};
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImCompleteDefinition {
+    // A heading in the same document
+    Local(Range<usize>),
+    // The `-- title` of another page, or the start
+    // of the file when it doesn't have one
+    Page(PathBuf, Range<usize>),
+}
 
-use crate::chumsky::{Expr, Func, Spanned};
-/// return (need_to_continue_search, founded reference)
+// `path` is the file the document was loaded from.
+// Links can point at a page id, a path relative to
+// the document or a `#heading` in the same document.
 pub fn get_definition(
-    ast: &HashMap<String, Func>,
-    ident_offset: usize,
-) -> Option<Spanned<String>> {
-    let mut vector = Vector::new();
-    for (_, v) in ast.iter() {
-        if v.name.1.start < ident_offset && v.name.1.end > ident_offset {
-            return Some(v.name.clone());
-        }
-        if v.name.1.end < ident_offset {
-            vector.push_back(v.name.clone());
-        }
+    document: &Document,
+    path: &Path,
+    offset: usize,
+    index: &WorkspaceIndex,
+) -> Option<ImCompleteDefinition> {
+    let target = link_at(document, offset)?.link_target()?.text();
+    if let Some(anchor) = target.strip_prefix('#') {
+        return heading_for_anchor(document, anchor)
+            .map(|section| ImCompleteDefinition::Local(header_span(section)));
+    }
+    let target_path = resolve_link(&target, path, index)?;
+    let span = match index.get(&target_path) {
+        Some(page) => page.document.title().map(header_span).unwrap_or(0..0),
+        None if target_path.is_file() => 0..0,
+        None => return None,
+    };
+    Some(ImCompleteDefinition::Page(target_path, span))
+}
+
+// The page a link target points to. Ids are tried
+// first then paths relative to the linking page.
+pub fn resolve_link(target: &str, from: &Path, index: &WorkspaceIndex) -> Option<PathBuf> {
+    if is_external(target) || target.starts_with('#') {
+        return None;
+    }
+    match index.pages_with_id(target).into_iter().next() {
+        Some((path, _)) => Some(path),
+        None => resolve_relative(target, from),
     }
+}
+
+pub fn is_external(target: &str) -> bool {
+    target.contains("://") || target.starts_with("mailto:")
+}
 
-    for (_, v) in ast.iter() {
-        let args = v.args.iter().cloned().collect::<Vector<_>>();
-        match get_definition_of_expr(&v.body, args + vector.clone(), ident_offset) {
-            (_, Some(value)) => {
-                return Some(value);
-            }
-            _ => {}
-        }
+pub fn resolve_relative(target: &str, from: &Path) -> Option<PathBuf> {
+    if !target.contains('/') && !target.ends_with(".neo") {
+        return None;
     }
-    None
+    Some(normalize_path(&from.parent()?.join(target)))
 }
 
-pub fn get_definition_of_expr(
-    expr: &Spanned<Expr>,
-    definition_ass_list: Vector<Spanned<String>>,
-    ident_offset: usize,
-) -> (bool, Option<Spanned<String>>) {
-    match &expr.0 {
-        Expr::Error => (true, None),
-        Expr::Value(_) => (true, None),
-        // Expr::List(exprs) => exprs
-        //     .iter()
-        //     .for_each(|expr| get_definition(expr, definition_ass_list)),
-        Expr::Local(local) => {
-            if ident_offset >= local.1.start && ident_offset < local.1.end {
-                let index = definition_ass_list
-                    .iter()
-                    .position(|decl| decl.0 == local.0);
-                (
-                    false,
-                    index.map(|i| definition_ass_list.get(i).unwrap().clone()),
-                )
-            } else {
-                (true, None)
-            }
-        }
-        Expr::Let(name, lhs, rest, name_span) => {
-            let new_decl = Vector::unit((name.clone(), name_span.clone()));
+// The innermost link tag around the cursor
+pub fn link_at(document: &Document, offset: usize) -> Option<&InlineTag> {
+    document
+        .inline_tags()
+        .into_iter()
+        .rev()
+        .filter(|tag| tag.span.start <= offset && offset < tag.span.end)
+        .find(|tag| tag.link_target().is_some())
+}
+
+// `-- id:` on the heading wins over the slug of
+// the heading text
+pub fn heading_for_anchor<'a>(document: &'a Document, anchor: &str) -> Option<&'a Section> {
+    let headings = document
+        .all_sections()
+        .into_iter()
+        .filter(|section| heading_level(&section.name).is_some())
+        .collect::<Vec<_>>();
+    headings
+        .iter()
+        .find(|section| {
+            section
+                .attribute("id")
+                .and_then(|attr| attr.value.as_ref())
+                .is_some_and(|value| value.text == anchor)
+        })
+        .or_else(|| {
+            headings.iter().find(|section| {
+                section
+                    .paragraphs()
+                    .first()
+                    .is_some_and(|p| heading_slug(&p.text()) == anchor)
+            })
+        })
+        .copied()
+}
+
+// Lowercase with everything other than letters and
+// numbers turned into single dashes
+pub fn heading_slug(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn header_span(section: &Section) -> Range<usize> {
+    section.span.start..section.name_span.end
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nom_parser::nom_parse;
+    use crate::registry::SectionRegistry;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    pub fn test_heading_slug() {
+        assert_eq!("alfa-bravo-2".to_string(), heading_slug("Alfa, Bravo 2!"));
+    }
+
+    #[test]
+    pub fn test_definition_from_id_link() {
+        let registry = SectionRegistry::default();
+        let index = WorkspaceIndex::default();
+        let bravo = PathBuf::from("/site/posts/bravo.neo");
+        index.insert(
+            bravo.clone(),
+            "-- metadata\n-- id: 2w3eqltz\n\n-- title\n\nBravo",
+            &registry,
+        );
+        let source = "-- p\n\nsee <<link|bravo|2w3eqltz>> for more";
+        let document = nom_parse(source, &registry).unwrap().1;
+        let path = Path::new("/site/alfa.neo");
+        assert_eq!(
+            Some(ImCompleteDefinition::Page(bravo, 29..37)),
+            get_definition(&document, path, 20, &index)
+        );
+        assert_eq!(None, get_definition(&document, path, 3, &index));
+    }
+
+    #[test]
+    pub fn test_definition_from_relative_path() {
+        let registry = SectionRegistry::default();
+        let index = WorkspaceIndex::default();
+        let bravo = PathBuf::from("/site/posts/bravo.neo");
+        index.insert(bravo.clone(), "-- title\n\nBravo", &registry);
+        let source = "-- p\n\n<<link|bravo|../posts/bravo.neo>>";
+        let document = nom_parse(source, &registry).unwrap().1;
+        assert_eq!(
+            Some(ImCompleteDefinition::Page(bravo, 0..8)),
+            get_definition(&document, Path::new("/site/drafts/alfa.neo"), 10, &index)
+        );
+    }
 
-            match get_definition_of_expr(lhs, definition_ass_list.clone(), ident_offset) {
-                (true, None) => {
-                    get_definition_of_expr(rest, new_decl + definition_ass_list, ident_offset)
-                }
-                (true, Some(value)) => (false, Some(value)),
-                (false, None) => (false, None),
-                (false, Some(value)) => (false, Some(value)),
-            }
-        }
-        Expr::Then(first, second) => {
-            match get_definition_of_expr(first, definition_ass_list.clone(), ident_offset) {
-                (true, None) => {
-                    get_definition_of_expr(second, definition_ass_list, ident_offset)
-                }
-                (false, None) => (false, None),
-                (true, Some(value)) | (false, Some(value)) => (false, Some(value)),
-            }
-        }
-        Expr::Binary(lhs, _, rhs) => {
-            match get_definition_of_expr(lhs, definition_ass_list.clone(), ident_offset) {
-                (true, None) => {
-                    get_definition_of_expr(rhs, definition_ass_list, ident_offset)
-                }
-                (false, None) => (false, None),
-                (true, Some(value)) | (false, Some(value)) => (false, Some(value)),
-            }
-        }
-        Expr::Call(callee, args) => {
-            match get_definition_of_expr(callee, definition_ass_list.clone(), ident_offset) {
-                (true, None) => {}
-                (true, Some(value)) => return (false, Some(value)),
-                (false, None) => return (false, None),
-                (false, Some(value)) => return (false, Some(value)),
-            }
-            for expr in &args.0 {
-                match get_definition_of_expr(expr, definition_ass_list.clone(), ident_offset) {
-                    (true, None) => continue,
-                    (true, Some(value)) => return (false, Some(value)),
-                    (false, None) => return (false, None),
-                    (false, Some(value)) => return (false, Some(value)),
-                }
-            }
-            (true, None)
-        }
-        Expr::If(test, consequent, alternative) => {
-            match get_definition_of_expr(test, definition_ass_list.clone(), ident_offset) {
-                (true, None) => {}
-                (true, Some(value)) => return (false, Some(value)),
-                (false, None) => return (false, None),
-                (false, Some(value)) => return (false, Some(value)),
-            }
-            match get_definition_of_expr(consequent, definition_ass_list.clone(), ident_offset) {
-                (true, None) => {}
-                (true, Some(value)) => return (false, Some(value)),
-                (false, None) => return (false, None),
-                (false, Some(value)) => return (false, Some(value)),
-            }
-            match get_definition_of_expr(alternative, definition_ass_list, ident_offset) {
-                (true, None) => (true, None),
-                (true, Some(value)) => (false, Some(value)),
-                (false, None) => (false, None),
-                (false, Some(value)) => (false, Some(value)),
-            }
-        }
-        Expr::Print(expr) => get_definition_of_expr(expr, definition_ass_list, ident_offset),
-        Expr::List(lst) => {
-            for expr in lst {
-                match get_definition_of_expr(expr, definition_ass_list.clone(), ident_offset) {
-                    (true, None) => continue,
-                    (true, Some(value)) => return (false, Some(value)),
-                    (false, None) => return (false, None),
-                    (false, Some(value)) => return (false, Some(value)),
-                }
-            }
-            (true, None)
-        }
+    #[test]
+    pub fn test_definition_from_anchor() {
+        let registry = SectionRegistry::default();
+        let index = WorkspaceIndex::default();
+        let source = "-- p\n\n<<link|below|#next-steps>>\n\n-- h2\n\nNext Steps";
+        let document = nom_parse(source, &registry).unwrap().1;
+        assert_eq!(
+            Some(ImCompleteDefinition::Local(34..39)),
+            get_definition(&document, Path::new("/site/alfa.neo"), 10, &index)
+        );
     }
 }
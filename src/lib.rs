@@ -1,7 +1,21 @@
+pub mod backlink;
 pub mod chumsky;
 pub mod completion;
+pub mod config;
+pub mod diagnostic;
+pub mod document_link;
+pub mod document_symbol;
+pub mod folding_range;
+pub mod hover;
 pub mod jump_definition;
+pub mod line_index;
+pub mod metadata;
+pub mod neo_ast;
 // pub mod neo_parser;
 pub mod nom_parser;
 pub mod reference;
+pub mod rename;
+pub mod registry;
 pub mod semantic_token;
+pub mod text_document;
+pub mod workspace;
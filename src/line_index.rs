@@ -0,0 +1,175 @@
+use std::ops::Range;
+
+use ropey::Rope;
+use tower_lsp::lsp_types::{self, Position, PositionEncodingKind};
+
+// How the client counts the `character` part of a
+// position. The parser always works in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionEncoding {
+    Utf8,
+    // Every client has to support this one
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    // The first encoding the client offers that the
+    // server knows, so the client's preference wins
+    pub fn negotiate(offered: &[PositionEncodingKind]) -> PositionEncoding {
+        offered
+            .iter()
+            .find_map(|kind| match kind.as_str() {
+                "utf-8" => Some(PositionEncoding::Utf8),
+                "utf-16" => Some(PositionEncoding::Utf16),
+                "utf-32" => Some(PositionEncoding::Utf32),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn kind(&self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+}
+
+// Converts between byte offsets in a document and
+// LSP positions in the negotiated encoding. Every
+// handler goes through this so the units can't get
+// mixed up.
+pub struct LineIndex<'a> {
+    rope: &'a Rope,
+    encoding: PositionEncoding,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(rope: &'a Rope, encoding: PositionEncoding) -> LineIndex<'a> {
+        LineIndex { rope, encoding }
+    }
+
+    pub fn position(&self, offset: usize) -> Option<Position> {
+        let line = self.rope.try_byte_to_line(offset).ok()?;
+        let line_start = self.rope.line_to_char(line);
+        let char = self.rope.try_byte_to_char(offset).ok()?;
+        let column = match self.encoding {
+            PositionEncoding::Utf8 => offset - self.rope.char_to_byte(line_start),
+            PositionEncoding::Utf16 => {
+                self.rope.char_to_utf16_cu(char) - self.rope.char_to_utf16_cu(line_start)
+            }
+            PositionEncoding::Utf32 => char - line_start,
+        };
+        Some(Position::new(line as u32, column as u32))
+    }
+
+    pub fn range(&self, span: &Range<usize>) -> Option<lsp_types::Range> {
+        Some(lsp_types::Range::new(
+            self.position(span.start)?,
+            self.position(span.end)?,
+        ))
+    }
+
+    // Columns past the end of the line are clamped to
+    // the end of the line like the spec asks for.
+    // Lines past the end of the document are `None`.
+    pub fn offset(&self, position: Position) -> Option<usize> {
+        let line = position.line as usize;
+        if line >= self.rope.len_lines() {
+            return None;
+        }
+        let line_start = self.rope.line_to_char(line);
+        let line_end = line_start + self.line_length(line);
+        let column = position.character as usize;
+        let char = match self.encoding {
+            PositionEncoding::Utf8 => {
+                let start = self.rope.char_to_byte(line_start);
+                let end = self.rope.char_to_byte(line_end);
+                self.rope.byte_to_char((start + column).min(end))
+            }
+            PositionEncoding::Utf16 => {
+                let start = self.rope.char_to_utf16_cu(line_start);
+                let end = self.rope.char_to_utf16_cu(line_end);
+                self.rope.utf16_cu_to_char((start + column).min(end))
+            }
+            PositionEncoding::Utf32 => (line_start + column).min(line_end),
+        };
+        Some(self.rope.char_to_byte(char))
+    }
+
+    // In chars, not counting the line break
+    fn line_length(&self, line: usize) -> usize {
+        self.rope
+            .get_line(line)
+            .map(|text| {
+                text.to_string()
+                    .trim_end_matches(['\n', '\r'])
+                    .chars()
+                    .count()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    pub fn test_negotiate() {
+        assert_eq!(PositionEncoding::Utf16, PositionEncoding::negotiate(&[]));
+        assert_eq!(
+            PositionEncoding::Utf8,
+            PositionEncoding::negotiate(&[
+                PositionEncodingKind::new("utf-7"),
+                PositionEncodingKind::UTF8,
+                PositionEncodingKind::UTF16,
+            ])
+        );
+    }
+
+    #[test]
+    pub fn test_position() {
+        // `é` is 2 bytes and 1 UTF-16 unit, `😀` is 4
+        // bytes and 2 UTF-16 units
+        let rope = Rope::from_str("-- p\n\né😀 alfa");
+        let alfa = "-- p\n\né😀 ".len();
+        let utf8 = LineIndex::new(&rope, PositionEncoding::Utf8);
+        let utf16 = LineIndex::new(&rope, PositionEncoding::Utf16);
+        let utf32 = LineIndex::new(&rope, PositionEncoding::Utf32);
+        assert_eq!(Some(Position::new(2, 7)), utf8.position(alfa));
+        assert_eq!(Some(Position::new(2, 4)), utf16.position(alfa));
+        assert_eq!(Some(Position::new(2, 3)), utf32.position(alfa));
+        assert_eq!(Some(alfa), utf8.offset(Position::new(2, 7)));
+        assert_eq!(Some(alfa), utf16.offset(Position::new(2, 4)));
+        assert_eq!(Some(alfa), utf32.offset(Position::new(2, 3)));
+        assert_eq!(Some(Position::new(1, 0)), utf16.position(5));
+    }
+
+    #[test]
+    pub fn test_offset_out_of_range() {
+        let rope = Rope::from_str("-- p\n\nalfa\n");
+        let index = LineIndex::new(&rope, PositionEncoding::Utf16);
+        assert_eq!(Some(4), index.offset(Position::new(0, 40)));
+        assert_eq!(Some(11), index.offset(Position::new(3, 0)));
+        assert_eq!(None, index.offset(Position::new(4, 0)));
+    }
+
+    #[test]
+    pub fn test_only_lsp_line_breaks() {
+        // U+2028 and form feed aren't line breaks to
+        // LSP so they stay in the middle of line 2
+        let rope = Rope::from_str("-- p\n\nalfa\u{2028}bravo\x0Ccharlie\n\ndelta");
+        let index = LineIndex::new(&rope, PositionEncoding::Utf16);
+        let charlie = "-- p\n\nalfa\u{2028}bravo\x0C".len();
+        let delta = "-- p\n\nalfa\u{2028}bravo\x0Ccharlie\n\n".len();
+        assert_eq!(Some(Position::new(2, 11)), index.position(charlie));
+        assert_eq!(Some(Position::new(4, 0)), index.position(delta));
+        assert_eq!(Some(charlie), index.offset(Position::new(2, 11)));
+        assert_eq!(Some(delta), index.offset(Position::new(4, 0)));
+    }
+}
@@ -1,87 +1,163 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
+use nrs_language_server::backlink::{backlinks, page_at};
 // use nrs_language_server::chumsky::{parse, type_inference, Func, ImCompleteSemanticToken};
-use nrs_language_server::chumsky::{type_inference, Func, ImCompleteSemanticToken};
-use nrs_language_server::completion::completion;
-use nrs_language_server::jump_definition::get_definition;
+use nrs_language_server::completion::{
+    completion, completion_prefix, ImCompleteCompletionItem, PathContext,
+};
+use nrs_language_server::config::Config;
+use nrs_language_server::diagnostic::diagnostics_from_document;
+use nrs_language_server::document_link::{
+    document_links, missing_file_diagnostics, ImCompleteLinkTarget,
+};
+use nrs_language_server::document_symbol::{document_symbols, ImCompleteSymbol};
+use nrs_language_server::folding_range::folding_ranges;
+use nrs_language_server::hover::hover;
+use nrs_language_server::jump_definition::{get_definition, ImCompleteDefinition};
+use nrs_language_server::line_index::{LineIndex, PositionEncoding};
+use nrs_language_server::metadata::{check_value, metadata_diagnostics, MetadataSchema};
 // use nrs_language_server::neo_parser::*;
-use nrs_language_server::nom_parser::*;
-use nrs_language_server::reference::get_reference;
-use nrs_language_server::semantic_token::{semantic_token_from_ast, LEGEND_TYPE};
+use nrs_language_server::neo_ast::Document;
+use nrs_language_server::nom_parser::nom_parse;
+use nrs_language_server::registry::SectionRegistry;
+use nrs_language_server::rename::{prepare_rename, rename_id, ImCompleteRenameTarget};
+use nrs_language_server::semantic_token::{
+    encode_semantic_tokens, legend, semantic_tokens_edits, semantic_tokens_from_document,
+    tokens_in_range, ImCompleteSemanticToken,
+};
+use nrs_language_server::text_document::apply_changes;
+use nrs_language_server::workspace::{duplicate_id, is_neo_file, WorkspaceIndex};
 use ropey::Rope;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tower_lsp::jsonrpc::Result;
+use tower_lsp::jsonrpc::{Error, Result};
 use tower_lsp::lsp_types::notification::Notification;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
 #[derive(Debug)]
 struct Backend {
     client: Client,
-    ast_map: DashMap<String, HashMap<String, Func>>,
     document_map: DashMap<String, Rope>,
+    document_versions: DashMap<String, i32>,
+    // Documents whose edits stopped lining up. Their
+    // incremental changes are dropped until the client
+    // sends the whole text again.
+    desynced: DashSet<String>,
+    position_encoding: RwLock<PositionEncoding>,
+    syntax_tree_map: DashMap<String, Document>,
     semantic_token_map: DashMap<String, Vec<ImCompleteSemanticToken>>,
+    // The last encoded tokens sent for each document
+    semantic_token_results: DashMap<String, CachedSemanticTokens>,
+    next_result_id: AtomicU64,
+    workspace_roots: RwLock<Vec<PathBuf>>,
+    registry: RwLock<SectionRegistry>,
+    metadata_schema: RwLock<MetadataSchema>,
+    asset_dirs: RwLock<Vec<PathBuf>>,
+    workspace_index: Arc<WorkspaceIndex>,
+    duplicate_id_pages: RwLock<HashSet<PathBuf>>,
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let roots = match params.workspace_folders {
+            Some(folders) => folders
+                .iter()
+                .filter_map(|folder| folder.uri.to_file_path().ok())
+                .collect::<Vec<_>>(),
+            None => params
+                .root_uri
+                .and_then(|uri| uri.to_file_path().ok())
+                .into_iter()
+                .collect::<Vec<_>>(),
+        };
+        *self.workspace_roots.write().unwrap() = roots;
+        let encoding = PositionEncoding::negotiate(
+            params
+                .capabilities
+                .general
+                .as_ref()
+                .and_then(|general| general.position_encodings.as_deref())
+                .unwrap_or_default(),
+        );
+        *self.position_encoding.write().unwrap() = encoding;
+        let client_token_types = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.semantic_tokens.as_ref())
+            .map(|semantic_tokens| semantic_tokens.token_types.clone())
+            .unwrap_or_default();
         Ok(InitializeResult {
             server_info: None,
             offset_encoding: None,
             capabilities: ServerCapabilities {
                 // inlay_hint_provider: Some(OneOf::Left(true)),
+                position_encoding: Some(encoding.kind()),
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
-                // completion_provider: Some(CompletionOptions {
-                //     resolve_provider: Some(false),
-                //     trigger_characters: Some(vec![".".to_string()]),
-                //     work_done_progress_options: Default::default(),
-                //     all_commit_characters: None,
-                //     completion_item: None,
-                // }),
+                completion_provider: Some(CompletionOptions {
+                    resolve_provider: Some(false),
+                    trigger_characters: Some(vec![" ".to_string(), "/".to_string()]),
+                    work_done_progress_options: Default::default(),
+                    all_commit_characters: None,
+                    completion_item: None,
+                }),
                 // execute_command_provider: Some(ExecuteCommandOptions {
                 //     commands: vec!["dummy.do_something".to_string()],
                 //     work_done_progress_options: Default::default(),
                 // }),
-                // workspace: Some(WorkspaceServerCapabilities {
-                //     workspace_folders: Some(WorkspaceFoldersServerCapabilities {
-                //         supported: Some(true),
-                //         change_notifications: Some(OneOf::Left(true)),
-                //     }),
-                //     file_operations: None,
-                // }),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: None,
+                }),
                 semantic_tokens_provider: Some(
                     SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(
                         SemanticTokensRegistrationOptions {
                             text_document_registration_options: {
                                 TextDocumentRegistrationOptions {
+                                    // By file name since editors don't agree
+                                    // on a language id for Neopolitan
                                     document_selector: Some(vec![DocumentFilter {
-                                        language: Some("org".to_string()),
+                                        language: None,
                                         scheme: Some("file".to_string()),
-                                        pattern: None,
+                                        pattern: Some("**/*.neo".to_string()),
                                     }]),
                                 }
                             },
                             semantic_tokens_options: SemanticTokensOptions {
                                 work_done_progress_options: WorkDoneProgressOptions::default(),
-                                legend: SemanticTokensLegend {
-                                    token_types: LEGEND_TYPE.into(),
-                                    token_modifiers: vec![],
-                                },
+                                legend: legend(&client_token_types),
                                 range: Some(true),
-                                full: Some(SemanticTokensFullOptions::Bool(true)),
+                                full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
                             },
                             static_registration_options: StaticRegistrationOptions::default(),
                         },
                     ),
                 ),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
                 // definition: Some(GotoCapability::default()),
-                // definition_provider: Some(OneOf::Left(true)),
-                // references_provider: Some(OneOf::Left(true)),
-                // rename_provider: Some(OneOf::Left(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: Default::default(),
+                }),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: Default::default(),
+                })),
                 ..ServerCapabilities::default()
             },
         })
@@ -90,6 +166,10 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "initialized!")
             .await;
+        self.load_config().await;
+        self.watch_neo_files().await;
+        let roots = self.workspace_roots.read().unwrap().clone();
+        self.scan_roots(roots).await;
     }
 
     async fn shutdown(&self) -> Result<()> {
@@ -100,21 +180,54 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "file opened!")
             .await;
+        self.desynced.remove(params.text_document.uri.as_str());
         self.on_change(TextDocumentItem {
             uri: params.text_document.uri,
-            text: params.text_document.text,
+            rope: Rope::from_str(&params.text_document.text),
             version: params.text_document.version,
         })
         .await
     }
 
-    async fn did_change(&self, mut params: DidChangeTextDocumentParams) {
-        self.on_change(TextDocumentItem {
-            uri: params.text_document.uri,
-            text: std::mem::take(&mut params.content_changes[0].text),
-            version: params.text_document.version,
-        })
-        .await
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        let uri = params.text_document.uri;
+        let version = params.text_document.version;
+        // Versions only go up so anything else is a
+        // change that has already been applied
+        if let Some(current) = self.document_versions.get(uri.as_str()).map(|v| *v) {
+            if version <= current {
+                self.client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!(
+                            "ignoring version {} of {}, already at {}",
+                            version, uri, current
+                        ),
+                    )
+                    .await;
+                return;
+            }
+        }
+        let mut changes = params.content_changes.as_slice();
+        if self.desynced.contains(uri.as_str()) {
+            // Only a full text change gets the document
+            // back. Everything before the last one is
+            // replaced by it anyway.
+            let Some(start) = changes.iter().rposition(|change| change.range.is_none()) else {
+                return;
+            };
+            changes = &changes[start..];
+        }
+        let rope = self.document_map.get(uri.as_str()).and_then(|rope| {
+            apply_changes(&rope, changes, *self.position_encoding.read().unwrap())
+        });
+        let Some(rope) = rope else {
+            self.mark_desynced(uri).await;
+            return;
+        };
+        self.desynced.remove(uri.as_str());
+        self.on_change(TextDocumentItem { uri, rope, version })
+            .await
     }
 
     async fn did_save(&self, _: DidSaveTextDocumentParams) {
@@ -122,65 +235,93 @@ impl LanguageServer for Backend {
             .log_message(MessageType::INFO, "file saved!")
             .await;
     }
-    async fn did_close(&self, _: DidCloseTextDocumentParams) {
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
         self.client
             .log_message(MessageType::INFO, "file closed!")
             .await;
+        let uri = params.text_document.uri;
+        self.document_map.remove(uri.as_str());
+        self.document_versions.remove(uri.as_str());
+        self.desynced.remove(uri.as_str());
+        self.syntax_tree_map.remove(uri.as_str());
+        self.semantic_token_map.remove(uri.as_str());
+        self.semantic_token_results.remove(uri.as_str());
+        // Unsaved changes are gone so the index goes
+        // back to what's on disk
+        if let Ok(path) = uri.to_file_path() {
+            match std::fs::read_to_string(&path) {
+                Ok(text) if is_neo_file(&path) => {
+                    self.workspace_index
+                        .insert(path, &text, &self.registry.read().unwrap())
+                }
+                _ => self.workspace_index.remove(&path),
+            }
+        }
+        self.publish_diagnostics(uri.clone(), None).await;
+        self.refresh_duplicate_ids(Some(&uri)).await;
     }
 
     async fn goto_definition(
         &self,
         params: GotoDefinitionParams,
     ) -> Result<Option<GotoDefinitionResponse>> {
-        let definition = async {
-            let uri = params.text_document_position_params.text_document.uri;
-            let ast = self.ast_map.get(uri.as_str())?;
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+        let definition = || -> Option<GotoDefinitionResponse> {
+            let document = self.syntax_tree_map.get(uri.as_str())?;
             let rope = self.document_map.get(uri.as_str())?;
-
-            let position = params.text_document_position_params.position;
-            let char = rope.try_line_to_char(position.line as usize).ok()?;
-            let offset = char + position.character as usize;
-            // self.client.log_message(MessageType::INFO, &format!("{:#?}, {}", ast.value(), offset)).await;
-            let span = get_definition(&ast, offset);
-            self.client
-                .log_message(MessageType::INFO, &format!("{:?}, ", span))
-                .await;
-            span.and_then(|(_, range)| {
-                let start_position = offset_to_position(range.start, &rope)?;
-                let end_position = offset_to_position(range.end, &rope)?;
-
-                let range = Range::new(start_position, end_position);
-
-                Some(GotoDefinitionResponse::Scalar(Location::new(uri, range)))
-            })
-        }
-        .await;
+            let path = uri.to_file_path().ok()?;
+            let offset = self.line_index(&rope).offset(position)?;
+            let location = match get_definition(&document, &path, offset, &self.workspace_index)? {
+                ImCompleteDefinition::Local(span) => {
+                    Location::new(uri.clone(), self.line_index(&rope).range(&span)?)
+                }
+                ImCompleteDefinition::Page(target, span) => {
+                    let range = match self.workspace_index.get(&target) {
+                        Some(page) => self.line_index(&page.rope).range(&span)?,
+                        None => Range::default(),
+                    };
+                    Location::new(Url::from_file_path(&target).ok()?, range)
+                }
+            };
+            Some(GotoDefinitionResponse::Scalar(location))
+        }();
         Ok(definition)
     }
     async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
-        let reference_list = || -> Option<Vec<Location>> {
-            let uri = params.text_document_position.text_document.uri;
-            let ast = self.ast_map.get(&uri.to_string())?;
-            let rope = self.document_map.get(&uri.to_string())?;
-
-            let position = params.text_document_position.position;
-            let char = rope.try_line_to_char(position.line as usize).ok()?;
-            let offset = char + position.character as usize;
-            let reference_list = get_reference(&ast, offset, false);
-            let ret = reference_list
-                .into_iter()
-                .filter_map(|(_, range)| {
-                    let start_position = offset_to_position(range.start, &rope)?;
-                    let end_position = offset_to_position(range.end, &rope)?;
-
-                    let range = Range::new(start_position, end_position);
-
-                    Some(Location::new(uri.clone(), range))
-                })
-                .collect::<Vec<_>>();
-            Some(ret)
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let references = || -> Option<Vec<Location>> {
+            let page = {
+                let document = self.syntax_tree_map.get(uri.as_str())?;
+                let rope = self.document_map.get(uri.as_str())?;
+                let offset = self.line_index(&rope).offset(position)?;
+                page_at(
+                    &document,
+                    &uri.to_file_path().ok()?,
+                    offset,
+                    &self.workspace_index,
+                )?
+            };
+            let mut locations = vec![];
+            if params.context.include_declaration {
+                if let Some(target) = self.workspace_index.get(&page) {
+                    if let Some(id) = target.document.metadata_id() {
+                        locations.push(Location::new(
+                            Url::from_file_path(&page).ok()?,
+                            self.line_index(&target.rope).range(&id.span)?,
+                        ));
+                    }
+                }
+            }
+            locations.extend(
+                self.backlinks_to(&page)
+                    .into_iter()
+                    .map(|backlink| Location::new(backlink.uri, backlink.range)),
+            );
+            Some(locations)
         }();
-        Ok(reference_list)
+        Ok(references)
     }
 
     async fn semantic_tokens_full(
@@ -188,51 +329,35 @@ impl LanguageServer for Backend {
         params: SemanticTokensParams,
     ) -> Result<Option<SemanticTokensResult>> {
         let uri = params.text_document.uri.to_string();
-        self.client
-            .log_message(MessageType::LOG, "semantic_token_full")
-            .await;
-        let semantic_tokens = || -> Option<Vec<SemanticToken>> {
-            let mut im_complete_tokens = self.semantic_token_map.get_mut(&uri)?;
-            let rope = self.document_map.get(&uri)?;
-            let ast = self.ast_map.get(&uri)?;
-            let extends_tokens = semantic_token_from_ast(&ast);
-            im_complete_tokens.extend(extends_tokens);
-            im_complete_tokens.sort_by(|a, b| a.start.cmp(&b.start));
-            let mut pre_line = 0;
-            let mut pre_start = 0;
-            let semantic_tokens = im_complete_tokens
-                .iter()
-                .filter_map(|token| {
-                    let line = rope.try_byte_to_line(token.start).ok()? as u32;
-                    let first = rope.try_line_to_char(line as usize).ok()? as u32;
-                    let start = rope.try_byte_to_char(token.start).ok()? as u32 - first;
-                    let delta_line = line - pre_line;
-                    let delta_start = if delta_line == 0 {
-                        start - pre_start
-                    } else {
-                        start
-                    };
-                    let ret = Some(SemanticToken {
-                        delta_line,
-                        delta_start,
-                        length: token.length as u32,
-                        token_type: token.token_type as u32,
-                        token_modifiers_bitset: 0,
-                    });
-                    pre_line = line;
-                    pre_start = start;
-                    ret
+        Ok(self
+            .semantic_tokens_result(&uri)
+            .map(|result| SemanticTokensResult::Tokens(result.to_lsp())))
+    }
+
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        let uri = params.text_document.uri.to_string();
+        let previous = self
+            .semantic_token_results
+            .get(&uri)
+            .map(|result| result.clone());
+        let Some(current) = self.semantic_tokens_result(&uri) else {
+            return Ok(None);
+        };
+        // Without the result the client has there's
+        // nothing to diff against
+        let response = match previous {
+            Some(previous) if previous.result_id == params.previous_result_id => {
+                SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+                    result_id: Some(current.result_id.clone()),
+                    edits: semantic_tokens_edits(&previous.data, &current.data),
                 })
-                .collect::<Vec<_>>();
-            Some(semantic_tokens)
-        }();
-        if let Some(semantic_token) = semantic_tokens {
-            return Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
-                result_id: None,
-                data: semantic_token,
-            })));
-        }
-        Ok(None)
+            }
+            _ => SemanticTokensFullDeltaResult::Tokens(current.to_lsp()),
+        };
+        Ok(Some(response))
     }
 
     async fn semantic_tokens_range(
@@ -243,105 +368,124 @@ impl LanguageServer for Backend {
         let semantic_tokens = || -> Option<Vec<SemanticToken>> {
             let im_complete_tokens = self.semantic_token_map.get(&uri)?;
             let rope = self.document_map.get(&uri)?;
-            let mut pre_line = 0;
-            let mut pre_start = 0;
-            let semantic_tokens = im_complete_tokens
-                .iter()
-                .filter_map(|token| {
-                    let line = rope.try_byte_to_line(token.start).ok()? as u32;
-                    let first = rope.try_line_to_char(line as usize).ok()? as u32;
-                    let start = rope.try_byte_to_char(token.start).ok()? as u32 - first;
-                    let ret = Some(SemanticToken {
-                        delta_line: line - pre_line,
-                        delta_start: if start >= pre_start {
-                            start - pre_start
-                        } else {
-                            start
-                        },
-                        length: token.length as u32,
-                        token_type: token.token_type as u32,
-                        token_modifiers_bitset: 0,
-                    });
-                    pre_line = line;
-                    pre_start = start;
-                    ret
-                })
-                .collect::<Vec<_>>();
-            Some(semantic_tokens)
+            let index = self.line_index(&rope);
+            let start = index.offset(params.range.start)?;
+            let end = index
+                .offset(params.range.end)
+                .unwrap_or_else(|| rope.len_bytes());
+            Some(encode_semantic_tokens(
+                tokens_in_range(&im_complete_tokens, start..end),
+                &index,
+            ))
         }();
-        if let Some(semantic_token) = semantic_tokens {
-            return Ok(Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
+        Ok(semantic_tokens.map(|data| {
+            SemanticTokensRangeResult::Tokens(SemanticTokens {
                 result_id: None,
-                data: semantic_token,
-            })));
-        }
-        Ok(None)
+                data,
+            })
+        }))
     }
 
-    async fn inlay_hint(
+    async fn document_symbol(
         &self,
-        params: tower_lsp::lsp_types::InlayHintParams,
-    ) -> Result<Option<Vec<InlayHint>>> {
-        self.client
-            .log_message(MessageType::INFO, "inlay hint")
-            .await;
-        let uri = &params.text_document.uri;
-        let mut hashmap = HashMap::new();
-        if let Some(ast) = self.ast_map.get(uri.as_str()) {
-            ast.iter().for_each(|(_, v)| {
-                type_inference(&v.body, &mut hashmap);
-            });
-        }
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        let uri = params.text_document.uri.to_string();
+        let symbols = || -> Option<Vec<DocumentSymbol>> {
+            let document = self.syntax_tree_map.get(&uri)?;
+            let rope = self.document_map.get(&uri)?;
+            let symbols = document_symbols(&document, &self.registry.read().unwrap());
+            let index = self.line_index(&rope);
+            Some(
+                symbols
+                    .iter()
+                    .filter_map(|symbol| to_document_symbol(symbol, &index))
+                    .collect(),
+            )
+        }();
+        Ok(symbols.map(DocumentSymbolResponse::Nested))
+    }
 
-        let document = match self.document_map.get(uri.as_str()) {
-            Some(rope) => rope,
-            None => return Ok(None),
-        };
-        let inlay_hint_list = hashmap
-            .into_iter()
-            .map(|(k, v)| {
-                (
-                    k.start,
-                    k.end,
-                    match v {
-                        nrs_language_server::chumsky::Value::Null => "null".to_string(),
-                        nrs_language_server::chumsky::Value::Bool(_) => "bool".to_string(),
-                        nrs_language_server::chumsky::Value::Num(_) => "number".to_string(),
-                        nrs_language_server::chumsky::Value::Str(_) => "string".to_string(),
-                        nrs_language_server::chumsky::Value::List(_) => "[]".to_string(),
-                        nrs_language_server::chumsky::Value::Func(_) => v.to_string(),
-                    },
-                )
-            })
-            .filter_map(|item| {
-                // let start_position = offset_to_position(item.0, document)?;
-                let end_position = offset_to_position(item.1, &document)?;
-                let inlay_hint = InlayHint {
-                    text_edits: None,
-                    tooltip: None,
-                    kind: Some(InlayHintKind::TYPE),
-                    padding_left: None,
-                    padding_right: None,
-                    data: None,
-                    position: end_position,
-                    label: InlayHintLabel::LabelParts(vec![InlayHintLabelPart {
-                        value: item.2,
-                        tooltip: None,
-                        location: Some(Location {
-                            uri: params.text_document.uri.clone(),
-                            range: Range {
-                                start: Position::new(0, 4),
-                                end: Position::new(0, 5),
-                            },
-                        }),
-                        command: None,
-                    }]),
-                };
-                Some(inlay_hint)
-            })
-            .collect::<Vec<_>>();
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let uri = params.text_document.uri.to_string();
+        let ranges = || -> Option<Vec<FoldingRange>> {
+            let document = self.syntax_tree_map.get(&uri)?;
+            let rope = self.document_map.get(&uri)?;
+            let index = self.line_index(&rope);
+            let mut ranges = folding_ranges(&document)
+                .into_iter()
+                .filter_map(|range| {
+                    let start_line = index.position(range.span.start)?.line;
+                    let end_line = index.position(range.span.end)?.line;
+                    (end_line > start_line).then_some(FoldingRange {
+                        start_line,
+                        start_character: None,
+                        end_line,
+                        end_character: None,
+                        kind: range.kind,
+                        collapsed_text: None,
+                    })
+                })
+                .collect::<Vec<_>>();
+            // A section and its raw body can cover
+            // the same lines
+            ranges.dedup_by(|a, b| a.start_line == b.start_line && a.end_line == b.end_line);
+            Some(ranges)
+        }();
+        Ok(ranges)
+    }
 
-        Ok(Some(inlay_hint_list))
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        let uri = params.text_document.uri;
+        let links = || -> Option<Vec<DocumentLink>> {
+            let document = self.syntax_tree_map.get(uri.as_str())?;
+            let rope = self.document_map.get(uri.as_str())?;
+            let roots = self.link_roots();
+            let links = document_links(
+                &document,
+                &uri.to_file_path().ok()?,
+                &roots,
+                &self.workspace_index,
+            );
+            Some(
+                links
+                    .into_iter()
+                    .filter_map(|link| {
+                        let target = match link.target {
+                            ImCompleteLinkTarget::Url(url) => Url::parse(&url).ok()?,
+                            ImCompleteLinkTarget::File(path) => Url::from_file_path(path).ok()?,
+                        };
+                        Some(DocumentLink {
+                            range: self.line_index(&rope).range(&link.span)?,
+                            target: Some(target),
+                            tooltip: None,
+                            data: None,
+                        })
+                    })
+                    .collect(),
+            )
+        }();
+        Ok(links)
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+        let hover = || -> Option<Hover> {
+            let document = self.syntax_tree_map.get(uri.as_str())?;
+            let rope = self.document_map.get(uri.as_str())?;
+            let offset = self.line_index(&rope).offset(position)?;
+            let item = hover(&document, offset, &self.registry.read().unwrap())?;
+            let range = self.line_index(&rope).range(&item.span)?;
+            Some(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: item.contents,
+                }),
+                range: Some(range),
+            })
+        }();
+        Ok(hover)
     }
 
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
@@ -349,82 +493,110 @@ impl LanguageServer for Backend {
         let position = params.text_document_position.position;
         let completions = || -> Option<Vec<CompletionItem>> {
             let rope = self.document_map.get(&uri.to_string())?;
-            let ast = self.ast_map.get(&uri.to_string())?;
-            let char = rope.try_line_to_char(position.line as usize).ok()?;
-            let offset = char + position.character as usize;
-            let completions = completion(&ast, offset);
-            let mut ret = Vec::with_capacity(completions.len());
-            for (_, item) in completions {
-                match item {
-                    nrs_language_server::completion::ImCompleteCompletionItem::Variable(var) => {
-                        ret.push(CompletionItem {
-                            label: var.clone(),
-                            insert_text: Some(var.clone()),
-                            kind: Some(CompletionItemKind::VARIABLE),
-                            detail: Some(var),
-                            ..Default::default()
-                        });
-                    }
-                    nrs_language_server::completion::ImCompleteCompletionItem::Function(
-                        name,
-                        args,
-                    ) => {
-                        ret.push(CompletionItem {
-                            label: name.clone(),
-                            kind: Some(CompletionItemKind::FUNCTION),
-                            detail: Some(name.clone()),
-                            insert_text: Some(format!(
-                                "{}({})",
-                                name,
-                                args.iter()
-                                    .enumerate()
-                                    .map(|(index, item)| { format!("${{{}:{}}}", index + 1, item) })
-                                    .collect::<Vec<_>>()
-                                    .join(",")
-                            )),
-                            insert_text_format: Some(InsertTextFormat::SNIPPET),
-                            ..Default::default()
-                        });
-                    }
-                }
-            }
+            let offset = self.line_index(&rope).offset(position)?;
+            let prefix = completion_prefix(&rope, offset)?;
+            let paths = PathContext {
+                document: uri.to_file_path().ok()?,
+                asset_dirs: self.asset_dirs.read().unwrap().clone(),
+            };
+            let completions = completion(
+                &prefix,
+                &self.registry.read().unwrap(),
+                &self.workspace_index,
+                &paths,
+            );
+            let ret = completions
+                .into_iter()
+                .map(|item| match item {
+                    ImCompleteCompletionItem::Section(name, docs, snippet) => CompletionItem {
+                        label: name.clone(),
+                        kind: Some(CompletionItemKind::CLASS),
+                        detail: Some(format!("-- {}", name)),
+                        documentation: Some(Documentation::MarkupContent(MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value: docs,
+                        })),
+                        insert_text: Some(snippet),
+                        insert_text_format: Some(InsertTextFormat::SNIPPET),
+                        ..Default::default()
+                    },
+                    ImCompleteCompletionItem::Attribute(key, docs) => CompletionItem {
+                        label: key.clone(),
+                        kind: Some(CompletionItemKind::PROPERTY),
+                        documentation: Some(Documentation::MarkupContent(MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value: docs,
+                        })),
+                        insert_text: Some(format!("{}: ", key)),
+                        ..Default::default()
+                    },
+                    ImCompleteCompletionItem::Value(value) => CompletionItem {
+                        label: value,
+                        kind: Some(CompletionItemKind::ENUM_MEMBER),
+                        ..Default::default()
+                    },
+                    ImCompleteCompletionItem::Path(name, true) => CompletionItem {
+                        label: name,
+                        kind: Some(CompletionItemKind::FOLDER),
+                        ..Default::default()
+                    },
+                    ImCompleteCompletionItem::Path(name, false) => CompletionItem {
+                        label: name,
+                        kind: Some(CompletionItemKind::FILE),
+                        ..Default::default()
+                    },
+                })
+                .collect::<Vec<_>>();
             Some(ret)
         }();
         Ok(completions.map(CompletionResponse::Array))
     }
 
-    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
-        let workspace_edit = || -> Option<WorkspaceEdit> {
-            let uri = params.text_document_position.text_document.uri;
-            let ast = self.ast_map.get(&uri.to_string())?;
-            let rope = self.document_map.get(&uri.to_string())?;
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        let prepare = || -> Option<PrepareRenameResponse> {
+            let rope = self.document_map.get(params.text_document.uri.as_str())?;
+            let target = self.rename_target(&params.text_document.uri, params.position)?;
+            Some(PrepareRenameResponse::RangeWithPlaceholder {
+                range: self.line_index(&rope).range(&target.span)?,
+                placeholder: target.id,
+            })
+        }();
+        Ok(prepare)
+    }
 
-            let position = params.text_document_position.position;
-            let char = rope.try_line_to_char(position.line as usize).ok()?;
-            let offset = char + position.character as usize;
-            let reference_list = get_reference(&ast, offset, true);
-            let new_name = params.new_name;
-            if !reference_list.is_empty() {
-                let edit_list = reference_list
-                    .into_iter()
-                    .filter_map(|(_, range)| {
-                        let start_position = offset_to_position(range.start, &rope)?;
-                        let end_position = offset_to_position(range.end, &rope)?;
-                        Some(TextEdit::new(
-                            Range::new(start_position, end_position),
-                            new_name.clone(),
-                        ))
-                    })
-                    .collect::<Vec<_>>();
-                let mut map = HashMap::new();
-                map.insert(uri, edit_list);
-                let workspace_edit = WorkspaceEdit::new(map);
-                Some(workspace_edit)
-            } else {
-                None
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let Some(target) = self.rename_target(&uri, position) else {
+            return Ok(None);
+        };
+        if let Some(value_type) = self.metadata_schema.read().unwrap().keys.get("id") {
+            if let Some(message) = check_value(&params.new_name, value_type) {
+                return Err(Error::invalid_params(format!("invalid id: {}", message)));
             }
-        }();
-        Ok(workspace_edit)
+        }
+        let edits = rename_id(&target, &params.new_name, &self.workspace_index)
+            .map_err(Error::invalid_params)?;
+        let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+        edits.into_iter().for_each(|edit| {
+            let Some(page) = self.workspace_index.get(&edit.path) else {
+                return;
+            };
+            let (Ok(uri), Some(range)) = (
+                Url::from_file_path(&edit.path),
+                self.line_index(&page.rope).range(&edit.span),
+            ) else {
+                return;
+            };
+            changes
+                .entry(uri)
+                .or_default()
+                .push(TextEdit::new(range, edit.new_text));
+        });
+        Ok(Some(WorkspaceEdit::new(changes)))
     }
 
     async fn did_change_configuration(&self, _: DidChangeConfigurationParams) {
@@ -433,16 +605,89 @@ impl LanguageServer for Backend {
             .await;
     }
 
-    async fn did_change_workspace_folders(&self, _: DidChangeWorkspaceFoldersParams) {
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
         self.client
             .log_message(MessageType::INFO, "workspace folders changed!")
             .await;
+        let removed = params
+            .event
+            .removed
+            .iter()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect::<Vec<_>>();
+        let added = params
+            .event
+            .added
+            .iter()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect::<Vec<_>>();
+        removed
+            .iter()
+            .for_each(|root| self.workspace_index.remove_root(root));
+        {
+            let mut roots = self.workspace_roots.write().unwrap();
+            roots.retain(|root| !removed.contains(root));
+            roots.extend(added.iter().cloned());
+        }
+        self.scan_roots(added).await;
     }
 
-    async fn did_change_watched_files(&self, _: DidChangeWatchedFilesParams) {
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
         self.client
             .log_message(MessageType::INFO, "watched files have changed!")
             .await;
+        for change in params.changes {
+            if let Ok(path) = change.uri.to_file_path() {
+                self.workspace_index.forget_file(&path);
+            }
+            // Open documents are kept current by
+            // did_change instead
+            if self.document_map.contains_key(change.uri.as_str()) {
+                continue;
+            }
+            let Ok(path) = change.uri.to_file_path() else {
+                continue;
+            };
+            if !is_neo_file(&path) {
+                continue;
+            }
+            match change.typ {
+                FileChangeType::DELETED => self.workspace_index.remove(&path),
+                _ => match std::fs::read_to_string(&path) {
+                    Ok(text) => {
+                        self.workspace_index
+                            .insert(path, &text, &self.registry.read().unwrap())
+                    }
+                    Err(_) => self.workspace_index.remove(&path),
+                },
+            }
+        }
+        self.refresh_duplicate_ids(None).await;
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        let symbols = self
+            .workspace_index
+            .symbols(&params.query)
+            .into_iter()
+            .filter_map(|symbol| {
+                let page = self.workspace_index.get(&symbol.path)?;
+                let range = self.line_index(&page.rope).range(&symbol.span)?;
+                #[allow(deprecated)]
+                Some(SymbolInformation {
+                    name: symbol.name,
+                    kind: symbol.kind,
+                    tags: None,
+                    deprecated: None,
+                    location: Location::new(Url::from_file_path(&symbol.path).ok()?, range),
+                    container_name: symbol.container,
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(Some(symbols))
     }
 
     async fn execute_command(&self, _: ExecuteCommandParams) -> Result<Option<Value>> {
@@ -459,366 +704,401 @@ impl LanguageServer for Backend {
         Ok(None)
     }
 }
+#[allow(dead_code)]
 #[derive(Debug, Deserialize, Serialize)]
 struct InlayHintParams {
     path: String,
 }
 
+#[allow(dead_code)]
 enum CustomNotification {}
 impl Notification for CustomNotification {
     type Params = InlayHintParams;
     const METHOD: &'static str = "custom/notification";
 }
-#[allow(dead_code)]
-struct TextDocumentItem {
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BacklinksParams {
+    text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Serialize)]
+struct Backlink {
     uri: Url,
+    range: Range,
+    // Title of the linking page
+    title: Option<String>,
+    // Link text
     text: String,
+}
+
+// Encoded tokens for one version of a document.
+// They're never changed, a new version gets a new
+// result with a new id.
+#[derive(Debug, Clone)]
+struct CachedSemanticTokens {
+    result_id: String,
+    version: i32,
+    data: Arc<Vec<SemanticToken>>,
+}
+
+impl CachedSemanticTokens {
+    fn to_lsp(&self) -> SemanticTokens {
+        SemanticTokens {
+            result_id: Some(self.result_id.clone()),
+            data: self.data.to_vec(),
+        }
+    }
+}
+
+struct TextDocumentItem {
+    uri: Url,
+    rope: Rope,
     version: i32,
 }
 
 impl Backend {
-    async fn on_change(&self, params: TextDocumentItem) {
-        let rope = ropey::Rope::from_str(&params.text);
-        self.document_map
-            .insert(params.uri.to_string(), rope.clone());
+    fn line_index<'a>(&self, rope: &'a Rope) -> LineIndex<'a> {
+        LineIndex::new(rope, *self.position_encoding.read().unwrap())
+    }
+
+    // `neopolitan/backlinks` lists every link to the
+    // page so editors can show them in a side panel
+    async fn backlinks(&self, params: BacklinksParams) -> Result<Vec<Backlink>> {
+        let Ok(path) = params.text_document.uri.to_file_path() else {
+            return Ok(vec![]);
+        };
+        Ok(self.backlinks_to(&path))
+    }
 
-        // let (base_tokens, errors) = neo_parse(&params.text);
+    fn rename_target(&self, uri: &Url, position: Position) -> Option<ImCompleteRenameTarget> {
+        let document = self.syntax_tree_map.get(uri.as_str())?;
+        let rope = self.document_map.get(uri.as_str())?;
+        let offset = self.line_index(&rope).offset(position)?;
+        prepare_rename(
+            &document,
+            &uri.to_file_path().ok()?,
+            offset,
+            &self.workspace_index,
+        )
+    }
 
-        let base_tokens = match nom_parse(&params.text) {
-            Ok((_, tokens)) => Some(tokens),
-            _ => None,
+    // Tokens are only encoded once per version of the
+    // document no matter how often they're asked for
+    fn semantic_tokens_result(&self, uri: &str) -> Option<CachedSemanticTokens> {
+        let version = self.document_versions.get(uri).map(|v| *v)?;
+        if let Some(cached) = self.semantic_token_results.get(uri) {
+            if cached.version == version {
+                return Some(cached.clone());
+            }
+        }
+        let data = {
+            let tokens = self.semantic_token_map.get(uri)?;
+            let rope = self.document_map.get(uri)?;
+            encode_semantic_tokens(&tokens, &self.line_index(&rope))
+        };
+        let result = CachedSemanticTokens {
+            result_id: self
+                .next_result_id
+                .fetch_add(1, Ordering::Relaxed)
+                .to_string(),
+            version,
+            data: Arc::new(data),
         };
+        self.semantic_token_results
+            .insert(uri.to_string(), result.clone());
+        Some(result)
+    }
 
-        let semantic_tokens = match base_tokens {
-            Some(tokens) => tokens
-                .iter()
-                .filter_map(|token| {
-                    dbg!(&token);
-                    match token {
-                        NomToken::ListDash(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::COMMENT)
-                                .unwrap(),
-                        }),
-
-                        // These are the original classes. TODO is to remove them in
-                        // favor of parer specific ones
-                        NomToken::Class(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::CLASS)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Comment(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::COMMENT)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Decorator(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                // .position(|item| item == &SemanticTokenType::COMMENT)
-                                .position(|item| item == &SemanticTokenType::DECORATOR)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Enum(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::ENUM)
-                                .unwrap(),
-                        }),
-
-                        NomToken::EnumMember(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::ENUM_MEMBER)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Event(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::EVENT)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Function(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::FUNCTION)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Interface(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::INTERFACE)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Keyword(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::KEYWORD)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Macro(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::MACRO)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Method(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::METHOD)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Modifier(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::MODIFIER)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Namespace(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::NAMESPACE)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Number(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::NUMBER)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Operator(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::OPERATOR)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Parameter(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::PARAMETER)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Property(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::PROPERTY)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Regexp(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::REGEXP)
-                                .unwrap(),
-                        }),
-
-                        NomToken::String(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::STRING)
-                                // .position(|item| item == &SemanticTokenType::DECORATOR)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Struct(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::STRUCT)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Type(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::TYPE)
-                                .unwrap(),
-                        }),
-
-                        NomToken::TypeParameter(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::TYPE_PARAMETER)
-                                .unwrap(),
-                        }),
-
-                        NomToken::Variable(_, start, end) => Some(ImCompleteSemanticToken {
-                            start: *start,
-                            length: end - start,
-                            token_type: LEGEND_TYPE
-                                .iter()
-                                .position(|item| item == &SemanticTokenType::VARIABLE)
-                                .unwrap(),
-                        }),
-                        _ => None,
-                    }
+    fn backlinks_to(&self, page: &Path) -> Vec<Backlink> {
+        backlinks(page, &self.workspace_index)
+            .into_iter()
+            .filter_map(|backlink| {
+                let source = self.workspace_index.get(&backlink.path)?;
+                Some(Backlink {
+                    uri: Url::from_file_path(&backlink.path).ok()?,
+                    range: self.line_index(&source.rope).range(&backlink.target_span)?,
+                    title: source.document.title().and_then(|title| {
+                        title.paragraphs().first().map(|paragraph| paragraph.text())
+                    }),
+                    text: backlink.text,
                 })
-                .collect::<Vec<_>>(),
-            None => vec![],
-        };
+            })
+            .collect()
+    }
 
-        // let semantic_tokens = base_tokens
-        // .iter()
-        // .filter_map(|token| {
-        //     dbg!(&token);
-        //     match token {
-        //         NomToken::Class(_, start, end) => Some(ImCompleteSemanticToken {
-        //             start: *start,
-        //             length: end - start,
-        //             token_type: LEGEND_TYPE
-        //                 .iter()
-        //                 .position(|item| item == &SemanticTokenType::CLASS)
-        //                 .unwrap(),
-        //         }),
-        //         NomToken::Decorator(_, start, end) => Some(ImCompleteSemanticToken {
-        //             start: *start,
-        //             length: end - start,
-        //             token_type: LEGEND_TYPE
-        //                 .iter()
-        //                 .position(|item| item == &SemanticTokenType::DECORATOR)
-        //                 .unwrap(),
-        //         }),
-        //         _  => None
-        //     }
-        // })
-        // .collect::<Vec<_>>();
-
-        let ast: Option<HashMap<String, Func>> = Some(HashMap::new());
-        // let _errors = Vec::new();
-        // let semantic_tokens = vec![];
-
-        // (Some(ast), Vec::new(), semantic_tokens)
-
-        // let (ast, _errors, semantic_tokens) = parse(&params.text);
-        // self.client
-        //     .log_message(MessageType::INFO, format!("{:?}", errors))
-        //     .await;
+    // Where site absolute links are looked up. Assets
+    // come first, then the workspace roots for pages.
+    fn link_roots(&self) -> Vec<PathBuf> {
+        let mut roots = self.asset_dirs.read().unwrap().clone();
+        self.workspace_roots
+            .read()
+            .unwrap()
+            .iter()
+            .for_each(|root| {
+                if !roots.contains(root) {
+                    roots.push(root.clone());
+                }
+            });
+        roots
+    }
 
-        // let diagnostics = errors
-        //     .into_iter()
-        //     .filter_map(|item| {
-        //         let (message, span) = match item.reason() {
-        //             chumsky::error::SimpleReason::Unclosed { span, delimiter } => {
-        //                 (format!("Unclosed delimiter {}", delimiter), span.clone())
-        //             }
-        //             chumsky::error::SimpleReason::Unexpected => (
-        //                 format!(
-        //                     "{}, expected {}",
-        //                     if item.found().is_some() {
-        //                         "Unexpected token in input"
-        //                     } else {
-        //                         "Unexpected end of input"
-        //                     },
-        //                     if item.expected().len() == 0 {
-        //                         "something else".to_string()
-        //                     } else {
-        //                         item.expected()
-        //                             .map(|expected| match expected {
-        //                                 Some(expected) => expected.to_string(),
-        //                                 None => "end of input".to_string(),
-        //                             })
-        //                             .collect::<Vec<_>>()
-        //                             .join(", ")
-        //                     }
-        //                 ),
-        //                 item.span(),
-        //             ),
-        //             chumsky::error::SimpleReason::Custom(msg) => (msg.to_string(), item.span()),
-        //         };
-        //         || -> Option<Diagnostic> {
-        //             // let start_line = rope.try_char_to_line(*start)?;
-        //             // let first_char = rope.try_line_to_char(start_line)?;
-        //             // let start_column = *start - first_char;
-        //             let start_position = offset_to_position(*start, &rope)?;
-        //             let end_position = offset_to_position(span.end, &rope)?;
-        //             // let end_line = rope.try_char_to_line(span.end)?;
-        //             // let first_char = rope.try_line_to_char(end_line)?;
-        //             // let end_column = span.end - first_char;
-        //             Some(Diagnostic::new_simple(
-        //                 Range::new(start_position, end_position),
-        //                 message,
-        //             ))
-        //         }()
-        //     })
-        //     .collect::<Vec<_>>();
+    async fn load_config(&self) {
+        let roots = self.workspace_roots.read().unwrap().clone();
+        let mut registry = SectionRegistry::default();
+        let mut metadata_schema = MetadataSchema::default();
+        let mut asset_dirs = vec![];
+        for root in roots {
+            match Config::load(&root) {
+                Ok(Some(config)) => {
+                    match config.assets.is_empty() {
+                        true => asset_dirs.push(root.clone()),
+                        false => asset_dirs.extend(config.assets.iter().map(|dir| root.join(dir))),
+                    }
+                    registry.extend(config.sections);
+                    if let Some(schema) = config.metadata {
+                        metadata_schema = schema;
+                    }
+                }
+                Ok(None) => asset_dirs.push(root.clone()),
+                Err(message) => {
+                    self.client
+                        .show_message(MessageType::WARNING, message)
+                        .await
+                }
+            }
+        }
+        *self.registry.write().unwrap() = registry;
+        *self.metadata_schema.write().unwrap() = metadata_schema;
+        *self.asset_dirs.write().unwrap() = asset_dirs;
+    }
 
-        // self.client
-        //     .publish_diagnostics(params.uri.clone(), diagnostics, Some(params.version))
-        //     .await;
+    async fn watch_neo_files(&self) {
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String("**/*.neo".to_string()),
+                    kind: None,
+                },
+                // Linked files only matter for whether
+                // they exist
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String("**/*".to_string()),
+                    kind: Some(WatchKind::Create | WatchKind::Delete),
+                },
+            ],
+        };
+        let registration = Registration {
+            id: "neo-files".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(options).ok(),
+        };
+        if let Err(err) = self.client.register_capability(vec![registration]).await {
+            self.client.log_message(MessageType::WARNING, err).await;
+        }
+    }
 
-        if let Some(ast) = ast {
-            self.ast_map.insert(params.uri.to_string(), ast);
+    // Reading thousands of pages takes a while so it's
+    // done off the async runtime
+    async fn scan_roots(&self, roots: Vec<PathBuf>) {
+        let index = self.workspace_index.clone();
+        let registry = self.registry.read().unwrap().clone();
+        let scan = tokio::task::spawn_blocking(move || {
+            roots.iter().for_each(|root| index.scan(root, &registry));
+        });
+        if let Err(err) = scan.await {
+            self.client
+                .log_message(MessageType::ERROR, err.to_string())
+                .await;
         }
+        // The scan read files from disk so documents
+        // opened in the meantime go back to the text in
+        // the editor
+        self.document_map.iter().for_each(|entry| {
+            let Some(path) = Url::parse(entry.key())
+                .ok()
+                .and_then(|uri| uri.to_file_path().ok())
+                .filter(|path| is_neo_file(path))
+            else {
+                return;
+            };
+            if let Some(document) = self.syntax_tree_map.get(entry.key()) {
+                self.workspace_index
+                    .insert_document(path, entry.value().clone(), document.clone());
+            }
+        });
+        self.client
+            .log_message(
+                MessageType::INFO,
+                format!("indexed {} pages", self.workspace_index.len()),
+            )
+            .await;
+        self.refresh_duplicate_ids(None).await;
+    }
+
+    async fn on_change(&self, params: TextDocumentItem) {
+        let rope = params.rope;
+        self.document_map
+            .insert(params.uri.to_string(), rope.clone());
+        self.document_versions
+            .insert(params.uri.to_string(), params.version);
+
+        let text = rope.to_string();
+        let document = match nom_parse(&text, &self.registry.read().unwrap()) {
+            Ok((_, document)) => document,
+            _ => Document::default(),
+        };
+        let semantic_tokens =
+            semantic_tokens_from_document(&document, &self.registry.read().unwrap());
+        // Other pages only need republishing when this
+        // page's id changed
+        let mut id_changed = false;
+        if let Ok(path) = params.uri.to_file_path() {
+            if is_neo_file(&path) {
+                let previous_id = self
+                    .workspace_index
+                    .get(&path)
+                    .and_then(|page| page.document.metadata_id().map(|id| id.text.clone()));
+                id_changed = previous_id.as_ref() != document.metadata_id().map(|id| &id.text);
+                self.workspace_index
+                    .insert_document(path, rope.clone(), document.clone());
+            }
+        }
+        self.syntax_tree_map
+            .insert(params.uri.to_string(), document);
 
         // self.client
         //     .log_message(MessageType::INFO, &format!("{:?}", semantic_tokens))
         //     .await;
         self.semantic_token_map
             .insert(params.uri.to_string(), semantic_tokens);
+
+        self.publish_diagnostics(params.uri.clone(), Some(params.version))
+            .await;
+        if id_changed {
+            self.refresh_duplicate_ids(Some(&params.uri)).await;
+        }
+    }
+
+    // The edits didn't line up with the text we have.
+    // Patching on top of anything else (like the file
+    // on disk) would drift further from the editor, so
+    // the document keeps its last good state and stops
+    // taking edits until the client sends all of it.
+    async fn mark_desynced(&self, uri: Url) {
+        self.client
+            .log_message(
+                MessageType::ERROR,
+                format!(
+                    "could not apply changes to {}, ignoring edits until it's reopened",
+                    uri
+                ),
+            )
+            .await;
+        self.desynced.insert(uri.to_string());
+        self.client
+            .show_message(
+                MessageType::WARNING,
+                "The document got out of sync with the language server. Reopen it to fix this.",
+            )
+            .await;
+    }
+
+    // Open documents get everything. Pages that
+    // aren't open only get duplicate id errors since
+    // those come from other pages.
+    async fn publish_diagnostics(&self, uri: Url, version: Option<i32>) {
+        let mut diagnostics = vec![];
+        if let (Some(document), Some(rope)) = (
+            self.syntax_tree_map.get(uri.as_str()),
+            self.document_map.get(uri.as_str()),
+        ) {
+            let mut items = diagnostics_from_document(&document);
+            items.extend(metadata_diagnostics(
+                &document,
+                &self.metadata_schema.read().unwrap(),
+            ));
+            if let Ok(path) = uri.to_file_path() {
+                let roots = self.link_roots();
+                items.extend(missing_file_diagnostics(
+                    &document,
+                    &path,
+                    &roots,
+                    &self.workspace_index,
+                ));
+            }
+            diagnostics.extend(items.into_iter().filter_map(|item| {
+                let range = self.line_index(&rope).range(&item.span)?;
+                Some(Diagnostic {
+                    range,
+                    severity: Some(item.severity),
+                    source: Some("neopolitan".to_string()),
+                    message: item.message,
+                    ..Diagnostic::default()
+                })
+            }));
+        }
+        if let Some(diagnostic) = self.duplicate_id_diagnostic(&uri) {
+            diagnostics.push(diagnostic);
+        }
+        self.client
+            .publish_diagnostics(uri, diagnostics, version)
+            .await;
+    }
+
+    fn duplicate_id_diagnostic(&self, uri: &Url) -> Option<Diagnostic> {
+        let path = uri.to_file_path().ok()?;
+        let duplicate = duplicate_id(&path, &self.workspace_index)?;
+        let range = {
+            let page = self.workspace_index.get(&path)?;
+            self.line_index(&page.rope).range(&duplicate.span)?
+        };
+        let related = duplicate
+            .others
+            .iter()
+            .filter_map(|(other, span)| {
+                let page = self.workspace_index.get(other)?;
+                Some(DiagnosticRelatedInformation {
+                    location: Location::new(
+                        Url::from_file_path(other).ok()?,
+                        self.line_index(&page.rope).range(span)?,
+                    ),
+                    message: format!("`{}` is also used here", duplicate.id),
+                })
+            })
+            .collect::<Vec<_>>();
+        Some(Diagnostic {
+            range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            source: Some("neopolitan".to_string()),
+            message: format!(
+                "id `{}` is also used by {} other page(s)",
+                duplicate.id,
+                duplicate.others.len()
+            ),
+            related_information: Some(related),
+            ..Diagnostic::default()
+        })
+    }
+
+    // Republishes every page that has a duplicate id
+    // now or had one before the last change so the
+    // errors go away once the ids are fixed. `skip` has
+    // just been published.
+    async fn refresh_duplicate_ids(&self, skip: Option<&Url>) {
+        let current = self.workspace_index.pages_with_duplicate_ids();
+        let previous = std::mem::replace(
+            &mut *self.duplicate_id_pages.write().unwrap(),
+            current.clone(),
+        );
+        for path in current.union(&previous) {
+            let Ok(uri) = Url::from_file_path(path) else {
+                continue;
+            };
+            if Some(&uri) != skip {
+                self.publish_diagnostics(uri, None).await;
+            }
+        }
     }
 }
 
@@ -831,21 +1111,48 @@ async fn main() {
 
     let (service, socket) = LspService::build(|client| Backend {
         client,
-        ast_map: DashMap::new(),
         document_map: DashMap::new(),
+        document_versions: DashMap::new(),
+        desynced: DashSet::new(),
+        position_encoding: RwLock::new(PositionEncoding::default()),
+        syntax_tree_map: DashMap::new(),
         semantic_token_map: DashMap::new(),
+        semantic_token_results: DashMap::new(),
+        next_result_id: AtomicU64::new(0),
+        workspace_roots: RwLock::new(vec![]),
+        registry: RwLock::new(SectionRegistry::default()),
+        metadata_schema: RwLock::new(MetadataSchema::default()),
+        asset_dirs: RwLock::new(vec![]),
+        workspace_index: Arc::new(WorkspaceIndex::default()),
+        duplicate_id_pages: RwLock::new(HashSet::new()),
     })
+    .custom_method("neopolitan/backlinks", Backend::backlinks)
     .finish();
 
     serde_json::json!({"test": 20});
     Server::new(stdin, stdout, socket).serve(service).await;
 }
 
-fn offset_to_position(offset: usize, rope: &Rope) -> Option<Position> {
-    let line = rope.try_char_to_line(offset).ok()?;
-    let first_char_of_line = rope.try_line_to_char(line).ok()?;
-    let column = offset - first_char_of_line;
-    Some(Position::new(line as u32, column as u32))
+#[allow(deprecated)]
+fn to_document_symbol(symbol: &ImCompleteSymbol, index: &LineIndex) -> Option<DocumentSymbol> {
+    let range = index.range(&symbol.span)?;
+    let selection_range = index.range(&symbol.selection_span)?;
+    Some(DocumentSymbol {
+        name: symbol.name.clone(),
+        detail: symbol.detail.clone(),
+        kind: symbol.kind,
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range,
+        children: Some(
+            symbol
+                .children
+                .iter()
+                .filter_map(|child| to_document_symbol(child, index))
+                .collect(),
+        ),
+    })
 }
 
 /////////////////////////////////////////////////////////////////
@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use serde::Deserialize;
+use tower_lsp::lsp_types::DiagnosticSeverity;
+
+use crate::diagnostic::ImCompleteDiagnostic;
+use crate::neo_ast::{Document, Section};
+
+// What's allowed in `-- metadata`. The defaults
+// match the keys the site builder uses and can be
+// replaced with a `metadata` entry in the config.
+
+pub const DEFAULT_DATE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MetadataSchema {
+    pub required: Vec<String>,
+    pub keys: BTreeMap<String, ValueType>,
+    pub warn_unknown_keys: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ValueType {
+    Text,
+    // Only `%Y %m %d %H %M %S` are understood.
+    // Anything else in the format has to match
+    // exactly.
+    #[serde(rename = "datetime")]
+    DateTime {
+        #[serde(default = "default_date_time_format")]
+        format: String,
+    },
+    Enum {
+        values: Vec<String>,
+    },
+    Pattern {
+        pattern: Pattern,
+    },
+}
+
+// Compiled when the schema is read so a bad pattern
+// fails the config load instead of every check
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Pattern(Regex);
+
+impl TryFrom<String> for Pattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Regex::new(&pattern)
+            .map(Pattern)
+            .map_err(|e| format!("`{}` isn't a valid pattern: {}", pattern, e))
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Pattern {
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        self.0.is_match(value)
+    }
+}
+
+fn default_date_time_format() -> String {
+    DEFAULT_DATE_TIME_FORMAT.to_string()
+}
+
+impl Default for MetadataSchema {
+    fn default() -> Self {
+        let date = ValueType::DateTime {
+            format: default_date_time_format(),
+        };
+        MetadataSchema {
+            required: vec!["date".to_string(), "id".to_string()],
+            keys: BTreeMap::from([
+                ("date".to_string(), date.clone()),
+                (
+                    "id".to_string(),
+                    ValueType::Pattern {
+                        pattern: Pattern(Regex::new("^[a-z0-9]+$").unwrap()),
+                    },
+                ),
+                ("site".to_string(), ValueType::Text),
+                ("status".to_string(), ValueType::Text),
+                ("template".to_string(), ValueType::Text),
+                ("updated".to_string(), date),
+            ]),
+            warn_unknown_keys: true,
+        }
+    }
+}
+
+// Pages without a `-- metadata` section aren't checked
+pub fn metadata_diagnostics(
+    document: &Document,
+    schema: &MetadataSchema,
+) -> Vec<ImCompleteDiagnostic> {
+    document
+        .sections
+        .iter()
+        .filter(|section| section.name == "metadata")
+        .flat_map(|section| section_diagnostics(section, schema))
+        .collect()
+}
+
+fn section_diagnostics(section: &Section, schema: &MetadataSchema) -> Vec<ImCompleteDiagnostic> {
+    let mut diagnostics = schema
+        .required
+        .iter()
+        .filter(|key| section.attribute(key).is_none())
+        .map(|key| ImCompleteDiagnostic {
+            span: section.name_span.clone(),
+            message: format!("metadata is missing `-- {}:`", key),
+            severity: DiagnosticSeverity::ERROR,
+        })
+        .collect::<Vec<_>>();
+    section.attributes.iter().for_each(|attr| {
+        let Some(value_type) = schema.keys.get(&attr.key) else {
+            if schema.warn_unknown_keys && !schema.required.contains(&attr.key) {
+                diagnostics.push(ImCompleteDiagnostic {
+                    span: attr.key_span.clone(),
+                    message: format!("unknown metadata key `{}`", attr.key),
+                    severity: DiagnosticSeverity::WARNING,
+                });
+            }
+            return;
+        };
+        let Some(value) = &attr.value else {
+            if *value_type != ValueType::Text {
+                diagnostics.push(ImCompleteDiagnostic {
+                    span: attr.key_span.clone(),
+                    message: format!("`{}` needs a value", attr.key),
+                    severity: DiagnosticSeverity::ERROR,
+                });
+            }
+            return;
+        };
+        if let Some(message) = check_value(&value.text, value_type) {
+            diagnostics.push(ImCompleteDiagnostic {
+                span: value.span.clone(),
+                message: format!("invalid `{}`: {}", attr.key, message),
+                severity: DiagnosticSeverity::ERROR,
+            });
+        }
+    });
+    diagnostics
+}
+
+// Returns what's wrong with the value, if anything
+pub fn check_value(value: &str, value_type: &ValueType) -> Option<String> {
+    match value_type {
+        ValueType::Text => None,
+        ValueType::DateTime { format } => (!matches_date_time(value, format))
+            .then(|| format!("expected a date in the format `{}`", format)),
+        ValueType::Enum { values } => (!values.iter().any(|v| v == value)).then(|| {
+            format!(
+                "expected one of {}",
+                values
+                    .iter()
+                    .map(|v| format!("`{}`", v))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        }),
+        ValueType::Pattern { pattern } => (!pattern.is_match(value))
+            .then(|| format!("expected a match for `{}`", pattern.as_str())),
+    }
+}
+
+pub fn matches_date_time(value: &str, format: &str) -> bool {
+    let mut value = value;
+    let mut format_chars = format.chars();
+    while let Some(c) = format_chars.next() {
+        if c != '%' {
+            match value.strip_prefix(c) {
+                Some(rest) => value = rest,
+                None => return false,
+            }
+            continue;
+        }
+        let (digits, range) = match format_chars.next() {
+            Some('Y') => (4, 0..=9999),
+            Some('m') => (2, 1..=12),
+            Some('d') => (2, 1..=31),
+            Some('H') => (2, 0..=23),
+            Some('M') => (2, 0..=59),
+            Some('S') => (2, 0..=60),
+            _ => return false,
+        };
+        let Some(number) = value.get(..digits) else {
+            return false;
+        };
+        if !number.chars().all(|c| c.is_ascii_digit()) {
+            return false;
+        }
+        match number.parse::<u32>() {
+            Ok(number) if range.contains(&number) => value = &value[digits..],
+            _ => return false,
+        }
+    }
+    value.is_empty()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nom_parser::nom_parse;
+    use crate::registry::SectionRegistry;
+    use pretty_assertions::assert_eq;
+
+    fn diagnostics(source: &str, schema: &MetadataSchema) -> Vec<ImCompleteDiagnostic> {
+        let document = nom_parse(source, &SectionRegistry::default()).unwrap().1;
+        metadata_diagnostics(&document, schema)
+    }
+
+    #[test]
+    pub fn test_matches_date_time() {
+        assert!(matches_date_time(
+            "2023-09-28 22:00:49",
+            DEFAULT_DATE_TIME_FORMAT
+        ));
+        assert!(!matches_date_time("2023-09-28", DEFAULT_DATE_TIME_FORMAT));
+        assert!(!matches_date_time(
+            "2023-13-28 22:00:49",
+            DEFAULT_DATE_TIME_FORMAT
+        ));
+        assert!(!matches_date_time(
+            "2023-09-28 22:00:49x",
+            DEFAULT_DATE_TIME_FORMAT
+        ));
+        assert!(matches_date_time("28/09/2023", "%d/%m/%Y"));
+    }
+
+    #[test]
+    pub fn test_valid_metadata() {
+        let source = "-- metadata\n-- date: 2023-09-28 22:00:49 \n-- id: 2w3eqltz\t\n-- site: aws\n-- template: post\n-- status: unpublished";
+        assert_eq!(
+            Vec::<ImCompleteDiagnostic>::new(),
+            diagnostics(source, &MetadataSchema::default())
+        );
+    }
+
+    #[test]
+    pub fn test_invalid_metadata() {
+        let source = "-- metadata\n-- date: yesterday\n-- id: 2W3E\n-- color: red";
+        let left = vec![
+            ImCompleteDiagnostic {
+                span: 21..30,
+                message: "invalid `date`: expected a date in the format `%Y-%m-%d %H:%M:%S`"
+                    .to_string(),
+                severity: DiagnosticSeverity::ERROR,
+            },
+            ImCompleteDiagnostic {
+                span: 38..42,
+                message: "invalid `id`: expected a match for `^[a-z0-9]+$`".to_string(),
+                severity: DiagnosticSeverity::ERROR,
+            },
+            ImCompleteDiagnostic {
+                span: 46..51,
+                message: "unknown metadata key `color`".to_string(),
+                severity: DiagnosticSeverity::WARNING,
+            },
+        ];
+        assert_eq!(left, diagnostics(source, &MetadataSchema::default()));
+    }
+
+    #[test]
+    pub fn test_schema_from_config() {
+        let schema: MetadataSchema = serde_json::from_str(
+            r#"{ "required": ["status"], "keys": { "status": { "type": "enum", "values": ["draft", "published"] } } }"#,
+        )
+        .unwrap();
+        assert!(schema.warn_unknown_keys);
+        let left = vec![ImCompleteDiagnostic {
+            span: 23..28,
+            message: "invalid `status`: expected one of `draft`, `published`".to_string(),
+            severity: DiagnosticSeverity::ERROR,
+        }];
+        assert_eq!(left, diagnostics("-- metadata\n-- status: ready", &schema));
+        let left = vec![
+            ImCompleteDiagnostic {
+                span: 3..11,
+                message: "metadata is missing `-- status:`".to_string(),
+                severity: DiagnosticSeverity::ERROR,
+            },
+            ImCompleteDiagnostic {
+                span: 15..19,
+                message: "unknown metadata key `site`".to_string(),
+                severity: DiagnosticSeverity::WARNING,
+            },
+        ];
+        assert_eq!(left, diagnostics("-- metadata\n-- site: aws", &schema));
+    }
+
+    #[test]
+    pub fn test_invalid_pattern_in_config() {
+        let error = serde_json::from_str::<MetadataSchema>(
+            r#"{ "keys": { "id": { "type": "pattern", "pattern": "[a-z" } } }"#,
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("`[a-z` isn't a valid pattern"));
+    }
+}
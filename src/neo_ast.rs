@@ -0,0 +1,336 @@
+use std::ops::Range;
+
+// The document model produced by `nom_parser::nom_parse`.
+// Every node carries the byte offsets it covers in
+// the source text so the LSP features can map them
+// back to positions.
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    pub sections: Vec<Section>,
+    pub errors: Vec<ErrorNode>,
+}
+
+// A chunk of the source that couldn't be parsed
+// as a section. The span runs to the point where
+// the parser picked back up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorNode {
+    pub span: Range<usize>,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    UnknownSection {
+        name: String,
+        name_span: Range<usize>,
+    },
+    MissingBlankLine {
+        name: String,
+        name_span: Range<usize>,
+    },
+    InvalidSection {
+        name: String,
+        name_span: Range<usize>,
+    },
+    UnclosedBlock {
+        name: String,
+        name_span: Range<usize>,
+    },
+    MismatchedBlockEnd {
+        expected: String,
+        found: String,
+        name_span: Range<usize>,
+    },
+    UnexpectedBlockEnd {
+        name: String,
+        name_span: Range<usize>,
+    },
+    UnclosedInlineTag {
+        open: Range<usize>,
+    },
+    // Usually a missing colon like `-- src a.jpg`
+    InvalidAttribute {
+        key: String,
+        key_span: Range<usize>,
+    },
+    UnexpectedContent,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub span: Range<usize>,
+    pub dashes: Range<usize>,
+    pub name: String,
+    pub name_span: Range<usize>,
+    pub attributes: Vec<Attribute>,
+    pub body: SectionBody,
+    pub block: Option<Block>,
+}
+
+// The extra parts of a section written as a block
+// (`-- div/` ... `-- /div`). `end` is `None` when
+// the block is never closed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub slash: Range<usize>,
+    pub children: Vec<Section>,
+    pub end: Option<BlockEnd>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockEnd {
+    pub span: Range<usize>,
+    pub dashes: Range<usize>,
+    pub slash: Range<usize>,
+    pub name: String,
+    pub name_span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SectionBody {
+    Empty,
+    Paragraphs(Vec<Paragraph>),
+    List(Vec<ListItem>),
+    Raw(RawBody),
+}
+
+// The body of a raw section (`code`, `pre`, etc.)
+// exactly as it was written, including indentation
+// and blank lines. Trailing blank lines before the
+// next section aren't part of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawBody {
+    pub span: Range<usize>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub span: Range<usize>,
+    pub dashes: Range<usize>,
+    pub key: String,
+    pub key_span: Range<usize>,
+    pub value: Option<AttributeValue>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeValue {
+    pub colon: Range<usize>,
+    pub text: String,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Paragraph {
+    pub span: Range<usize>,
+    pub content: Vec<Inline>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListItem {
+    pub span: Range<usize>,
+    pub dash: Range<usize>,
+    pub paragraphs: Vec<Paragraph>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inline {
+    Word(Word),
+    Tag(InlineTag),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub text: String,
+    pub span: Range<usize>,
+}
+
+// An inline tag like `<<alfa|strong>>`. The span
+// covers the angle brackets. Each parameter can
+// hold words and other tags.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlineTag {
+    pub span: Range<usize>,
+    pub params: Vec<TagParam>,
+    pub pipes: Vec<Range<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagParam {
+    pub span: Range<usize>,
+    pub content: Vec<Inline>,
+}
+
+// Used to tell which parameter of a tag is its
+// name. Neopolitan puts it second (`<<alfa|em>>`)
+// but links are often written name first
+// (`<<link|alfa|https://...>>`)
+pub const INLINE_TAG_NAMES: &[&str] = &[
+    "abbr", "b", "code", "del", "dfn", "em", "i", "ins", "kbd", "link", "mark", "q", "s", "samp",
+    "small", "span", "strong", "sub", "sup", "u", "var",
+];
+
+impl Document {
+    // Every section including the ones nested inside
+    // blocks, in document order
+    pub fn all_sections(&self) -> Vec<&Section> {
+        let mut response = vec![];
+        self.sections
+            .iter()
+            .for_each(|section| section.collect_sections(&mut response));
+        response
+    }
+
+    pub fn title(&self) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name == "title")
+    }
+
+    // The `-- id:` from the `-- metadata` section. This
+    // is what other pages use to link to this one.
+    pub fn metadata_id(&self) -> Option<&AttributeValue> {
+        self.sections
+            .iter()
+            .filter(|section| section.name == "metadata")
+            .find_map(|section| section.attribute("id")?.value.as_ref())
+    }
+
+    // Every inline tag in every paragraph, including
+    // tags nested inside other tags
+    pub fn inline_tags(&self) -> Vec<&InlineTag> {
+        let mut response = vec![];
+        self.all_sections()
+            .into_iter()
+            .flat_map(|section| section.paragraphs())
+            .for_each(|paragraph| collect_tags(&paragraph.content, &mut response));
+        response
+    }
+}
+
+fn collect_tags<'a>(content: &'a [Inline], response: &mut Vec<&'a InlineTag>) {
+    content.iter().for_each(|item| {
+        if let Inline::Tag(tag) = item {
+            response.push(tag);
+            tag.params
+                .iter()
+                .for_each(|param| collect_tags(&param.content, response));
+        }
+    });
+}
+
+impl Section {
+    fn collect_sections<'a>(&'a self, response: &mut Vec<&'a Section>) {
+        response.push(self);
+        self.children()
+            .iter()
+            .for_each(|child| child.collect_sections(response));
+    }
+
+    pub fn children(&self) -> &[Section] {
+        self.block
+            .as_ref()
+            .map(|block| block.children.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn attribute(&self, key: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|attr| attr.key == key)
+    }
+
+    pub fn paragraphs(&self) -> Vec<&Paragraph> {
+        match &self.body {
+            SectionBody::Empty | SectionBody::Raw(_) => vec![],
+            SectionBody::Paragraphs(paragraphs) => paragraphs.iter().collect(),
+            SectionBody::List(items) => items.iter().flat_map(|i| i.paragraphs.iter()).collect(),
+        }
+    }
+}
+
+impl Paragraph {
+    // The words joined back together with single
+    // spaces. Used for things like heading names
+    // where the original line breaks don't matter
+    pub fn text(&self) -> String {
+        inline_text(&self.content)
+    }
+}
+
+impl Inline {
+    pub fn span(&self) -> &Range<usize> {
+        match self {
+            Inline::Word(word) => &word.span,
+            Inline::Tag(tag) => &tag.span,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match self {
+            Inline::Word(word) => word.text.clone(),
+            Inline::Tag(tag) => tag.text().map(|p| p.text()).unwrap_or_default(),
+        }
+    }
+}
+
+impl InlineTag {
+    fn name_index(&self) -> Option<usize> {
+        let is_name = |index: usize| {
+            self.params
+                .get(index)
+                .map(|p| INLINE_TAG_NAMES.contains(&p.text().as_str()))
+                .unwrap_or(false)
+        };
+        match self.params.len() {
+            0 | 1 => None,
+            _ if is_name(0) && !is_name(1) => Some(0),
+            _ => Some(1),
+        }
+    }
+
+    pub fn name(&self) -> Option<&TagParam> {
+        self.name_index().map(|i| &self.params[i])
+    }
+
+    // The part of the tag that shows up as text
+    pub fn text(&self) -> Option<&TagParam> {
+        match self.name_index() {
+            Some(0) => self.params.get(1),
+            _ => self.params.first(),
+        }
+    }
+
+    // Everything after the text and name. For links
+    // the first one is the target
+    pub fn attributes(&self) -> &[TagParam] {
+        self.params.get(2..).unwrap_or_default()
+    }
+
+    // The target of a `<<link|text|target>>` tag
+    pub fn link_target(&self) -> Option<&TagParam> {
+        match self.name()?.text().as_str() {
+            "link" => self.attributes().first(),
+            _ => None,
+        }
+    }
+}
+
+impl TagParam {
+    pub fn text(&self) -> String {
+        inline_text(&self.content)
+    }
+}
+
+fn inline_text(content: &[Inline]) -> String {
+    // Things that touch in the source (like a tag
+    // followed by punctuation) stay together
+    let mut text = String::new();
+    let mut previous_end = None;
+    content.iter().for_each(|item| {
+        if previous_end.is_some() && previous_end != Some(item.span().start) {
+            text.push(' ');
+        }
+        text.push_str(&item.text());
+        previous_end = Some(item.span().end);
+    });
+    text
+}
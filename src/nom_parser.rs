@@ -1,481 +1,795 @@
 #![allow(unused_imports)]
+use crate::neo_ast::*;
+use crate::registry::{BodyKind, BodyPresence, SectionRegistry};
 use nom::branch::alt;
 use nom::bytes::complete::is_a;
 use nom::bytes::complete::is_not;
 use nom::bytes::complete::tag;
+use nom::bytes::complete::take;
 use nom::character::complete::anychar;
+use nom::character::complete::line_ending;
 use nom::character::complete::multispace0;
-use nom::character::complete::newline;
 use nom::character::complete::none_of;
 use nom::character::complete::one_of;
 use nom::character::complete::space0;
 use nom::character::complete::space1;
+use nom::combinator::all_consuming;
+use nom::combinator::eof;
+use nom::combinator::map;
+use nom::combinator::not;
 use nom::combinator::opt;
+use nom::combinator::peek;
+use nom::combinator::recognize;
+use nom::combinator::value;
+use nom::multi::many0;
 use nom::multi::many1;
 use nom::multi::separated_list0;
 use nom::multi::separated_list1;
 use nom::sequence::pair;
+use nom::sequence::preceded;
 use nom::sequence::terminated;
 use nom::IResult;
 use nom_locate::{position, LocatedSpan};
+use std::ops::Range;
 
 type Span<'a> = LocatedSpan<&'a str>;
 
-#[derive(Debug, PartialEq)]
-pub enum NomToken {
-    Class(String, usize, usize),
-    Comment(String, usize, usize),
-    Decorator(String, usize, usize),
-    Enum(String, usize, usize),
-    EnumMember(String, usize, usize),
-    Event(String, usize, usize),
-    Function(String, usize, usize),
-    Interface(String, usize, usize),
-    Keyword(String, usize, usize),
-    Macro(String, usize, usize),
-    Method(String, usize, usize),
-    Modifier(String, usize, usize),
-    Namespace(String, usize, usize),
-    Number(String, usize, usize),
-    Operator(String, usize, usize),
-    Parameter(String, usize, usize),
-    Property(String, usize, usize),
-    Regexp(String, usize, usize),
-    String(String, usize, usize),
-    Struct(String, usize, usize),
-    Type(String, usize, usize),
-    TypeParameter(String, usize, usize),
-    Variable(String, usize, usize),
-    Whitespace,
+pub fn attributes(source: Span) -> IResult<Span, Vec<Attribute>> {
+    let (source, attr) = many1(terminated(attribute, opt(blank_line)))(source)?;
+    Ok((source, attr))
+}
 
-    //
-    // Starting to put in specific things
-    // to separate the tokens on nom
-    // from the output tokens to make them
-    // easier to test and change
-    ListDash(String, usize, usize),
+pub fn attribute(source: Span) -> IResult<Span, Attribute> {
+    let (source, attr) = alt((key_value_attribute, boolean_attribute))(source)?;
+    Ok((source, attr))
 }
 
-pub fn attributes(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, attr) = many1(terminated(attribute, opt(blank_line)))(source)?;
+pub fn block_end(source: Span) -> IResult<Span, BlockEnd> {
+    let (source, dashes) = dashes(source)?;
+    let (source, slash) = tag("/")(source)?;
+    let (source, name) = section_name(source)?;
+    let (source, _) = space0(source)?;
+    let (source, _) = alt((value((), line_ending), value((), eof)))(source)?;
     Ok((
         source,
-        attr.into_iter().flatten().collect::<Vec<NomToken>>(),
+        BlockEnd {
+            span: dashes.start..span_range(&name).end,
+            dashes,
+            slash: span_range(&slash),
+            name: name.to_string(),
+            name_span: span_range(&name),
+        },
     ))
 }
 
-pub fn attribute(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, attr) = alt((key_value_attribute, boolean_attribute))(source)?;
-    Ok((source, attr))
+pub fn block_section<'a>(
+    source: Span<'a>,
+    registry: &SectionRegistry,
+    errors: &mut Vec<ErrorNode>,
+) -> IResult<Span<'a>, Section> {
+    let (source, (dashes, name, slash)) = block_start(source)?;
+    let spec = registry
+        .get(name.fragment())
+        .ok_or_else(|| nom::Err::Error(nom::error::Error::new(name, nom::error::ErrorKind::Tag)))?;
+    let (source, attrs) = opt(attributes)(source)?;
+    let source = match spec.kind {
+        // Leading indentation is part of a raw body
+        BodyKind::Raw => many0(blank_line)(source)?.0,
+        _ => multispace0(source)?.0,
+    };
+    let (source, body) = match spec.kind {
+        BodyKind::List => {
+            let (source, items) = opt(separated_list1(empty_line, list_item))(source)?;
+            (source, items.map(SectionBody::List))
+        }
+        BodyKind::Metadata => (source, None),
+        // Only the end marker finishes a raw block so
+        // the body can hold lines that look like sections
+        BodyKind::Raw => {
+            let end_marker = format!("-- /{}", name.fragment());
+            let (source, raw) = raw_body(source, |line| line == end_marker)?;
+            (source, raw.map(SectionBody::Raw))
+        }
+        _ => {
+            let (source, paragraphs) = opt(paragraphs)(source)?;
+            (source, paragraphs.map(SectionBody::Paragraphs))
+        }
+    };
+    let (source, (children, end)) = sections(source, registry, Some(name.fragment()), errors)?;
+    let name_span = span_range(&name);
+    match &end {
+        None => errors.push(ErrorNode {
+            span: dashes.start..slash.end,
+            kind: ErrorKind::UnclosedBlock {
+                name: name.to_string(),
+                name_span: name_span.clone(),
+            },
+        }),
+        Some(end) if end.name != *name.fragment() => errors.push(ErrorNode {
+            span: end.span.clone(),
+            kind: ErrorKind::MismatchedBlockEnd {
+                expected: name.to_string(),
+                found: end.name.clone(),
+                name_span: end.name_span.clone(),
+            },
+        }),
+        Some(_) => {}
+    }
+    let body = body.unwrap_or(SectionBody::Empty);
+    // Unclosed blocks run to the end of whatever
+    // was inside them
+    let span_end = end
+        .as_ref()
+        .map(|e| e.span.end)
+        .or(children.last().map(|c| c.span.end))
+        .or(body_end(&body))
+        .or(attrs.as_ref().and_then(|a| a.last()).map(|a| a.span.end))
+        .unwrap_or(slash.end);
+    Ok((
+        source,
+        Section {
+            span: dashes.start..span_end,
+            dashes,
+            name: name.to_string(),
+            name_span,
+            attributes: attrs.unwrap_or_default(),
+            body,
+            block: Some(Block {
+                slash,
+                children,
+                end,
+            }),
+        },
+    ))
 }
 
-pub fn blank_line(source: Span) -> IResult<Span, Vec<NomToken>> {
+pub fn block_start(source: Span) -> IResult<Span, (Range<usize>, Span, Range<usize>)> {
+    let (source, dashes) = dashes(source)?;
+    let (source, name) = is_not(" \t\r\n/")(source)?;
+    let (source, slash) = tag("/")(source)?;
     let (source, _) = space0(source)?;
-    let (source, _) = newline(source)?;
-    Ok((source, vec![]))
-}
-
-pub fn boolean_attribute(source: Span) -> IResult<Span, Vec<NomToken>> {
-    // dbg!("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA");
-    // dbg!(source);
-    // let (source, _) = multispace0(source)?;
-    // let (source, _) = multispace0(source)?;
-    // dbg!("BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB");
-    // dbg!(source);
-    let (source, mut response) = dashes(source)?;
-    // dbg!("CCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCC");
-    // dbg!(source);
-    let (source, key_start) = position(source)?;
-    // dbg!("DDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDD");
-    // dbg!(source);
-    let (source, key_value) = is_not(":\n")(source)?;
-    // dbg!("EEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEE");
-    // dbg!(source);
-    // let (source, _) = space0(source)?;
-    // dbg!("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF");
-    // dbg!(source);
-    // let (source, _) = newline(source)?;
-    // dbg!("GGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGG");
-    // dbg!(source);
-    let (source, key_end) = position(source)?;
-    let key_attr = NomToken::Comment(
-        key_value.to_string(),
-        key_start.location_offset(),
-        key_end.location_offset(),
-    );
-    response.push(key_attr);
-    Ok((source, response))
+    let (source, _) = alt((value((), line_ending), value((), eof)))(source)?;
+    Ok((source, (dashes, name, span_range(&slash))))
 }
 
-pub fn code_type_section(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, mut response) = dashes(source)?;
-    let (source, start) = position(source)?;
-    let (source, name) = alt((tag("code"), tag("css"), tag("pre"), tag("script")))(source)?;
-    let (source, end) = position(source)?;
-    response.push(NomToken::Class(
-        name.to_string(),
-        start.location_offset(),
-        end.location_offset(),
-    ));
+fn body_end(body: &SectionBody) -> Option<usize> {
+    match body {
+        SectionBody::Empty => None,
+        SectionBody::Paragraphs(paragraphs) => paragraphs.last().map(|p| p.span.end),
+        SectionBody::List(items) => items.last().map(|i| i.span.end),
+        SectionBody::Raw(raw) => Some(raw.span.end),
+    }
+}
+
+pub fn blank_line(source: Span) -> IResult<Span, ()> {
     let (source, _) = space0(source)?;
-    let (source, _) = newline(source)?;
-    let (source, attrs) = opt(separated_list0(tag("\n"), attribute))(source)?;
-    if let Some(attrs) = attrs {
-        response.append(&mut attrs.into_iter().flatten().collect::<Vec<NomToken>>());
-    }
-    let (source, _) = empty_line(source)?;
-    let (source, mut paragraphs) = paragraphs(source)?;
-    response.append(&mut paragraphs);
-    Ok((source, response))
+    let (source, _) = line_ending(source)?;
+    Ok((source, ()))
 }
 
-pub fn dashes(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, start) = position(source)?;
-    let (source, _) = tag("--")(source)?;
-    let (source, end) = position(source)?;
-    let (source, _) = tag(" ")(source)?;
+pub fn boolean_attribute(source: Span) -> IResult<Span, Attribute> {
+    let (source, dashes) = dashes(source)?;
+    let (_, line) = is_not(":\r\n")(source)?;
+    let (source, key) = take(line.fragment().trim_end().len())(source)?;
+    let (source, _) = space0(source)?;
+    Ok((
+        source,
+        Attribute {
+            span: dashes.start..span_range(&key).end,
+            dashes,
+            key: key.to_string(),
+            key_span: span_range(&key),
+            value: None,
+        },
+    ))
+}
+
+pub fn code_type_section<'a>(
+    source: Span<'a>,
+    registry: &SectionRegistry,
+) -> IResult<Span<'a>, Section> {
+    let (source, dashes) = dashes(source)?;
+    let (source, name) = section_name(source)?;
+    let (source, _) = space0(source)?;
+    let (source, _) = line_ending(source)?;
+    let (source, attrs) = opt(attributes)(source)?;
+    let (source, _) = blank_line(source)?;
+    let (source, _) = many0(blank_line)(source)?;
+    let (source, raw) = raw_body(source, |line| is_section_line(line, registry))?;
+    let end = raw
+        .as_ref()
+        .map(|raw| raw.span.end)
+        .or(attrs.as_ref().and_then(|a| a.last()).map(|a| a.span.end))
+        .unwrap_or(name.location_offset() + name.fragment().len());
     Ok((
         source,
-        vec![NomToken::Decorator(
-            "--".to_string(),
-            start.location_offset(),
-            end.location_offset(),
-        )],
+        Section {
+            span: dashes.start..end,
+            dashes,
+            name: name.to_string(),
+            name_span: span_range(&name),
+            attributes: attrs.unwrap_or_default(),
+            body: raw.map(SectionBody::Raw).unwrap_or(SectionBody::Empty),
+            block: None,
+        },
     ))
 }
 
-pub fn empty_line(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, _) = tag("\n")(source)?;
+pub fn dashes(source: Span) -> IResult<Span, Range<usize>> {
+    let (source, dashes) = tag("--")(source)?;
+    let (source, _) = tag(" ")(source)?;
+    Ok((source, span_range(&dashes)))
+}
+
+pub fn empty_line(source: Span) -> IResult<Span, ()> {
+    let (source, _) = space0(source)?;
+    let (source, _) = line_ending(source)?;
     let (source, _) = space0(source)?;
-    let (source, _) = tag("\n")(source)?;
+    let (source, _) = line_ending(source)?;
     let (source, _) = multispace0(source)?;
-    Ok((source, vec![NomToken::Whitespace]))
+    Ok((source, ()))
 }
 
-pub fn following_word_chars(source: Span) -> IResult<Span, Vec<NomToken>> {
+pub fn error_node<'a>(
+    source: Span<'a>,
+    registry: &SectionRegistry,
+) -> IResult<Span<'a>, ErrorNode> {
+    let (source, chunk) = take(resync_offset(source.fragment(), registry))(source)?;
+    let content = chunk.fragment().trim_end();
+    let span = chunk.location_offset()..chunk.location_offset() + content.len();
+    let kind = match section_header(chunk) {
+        Ok((rest, name)) => {
+            let name_span = span_range(&name);
+            let name = name.to_string();
+            if registry.get(name.trim_end_matches('/')).is_none() {
+                ErrorKind::UnknownSection { name, name_span }
+            } else if missing_blank_line(rest.fragment()) {
+                ErrorKind::MissingBlankLine { name, name_span }
+            } else {
+                ErrorKind::InvalidSection { name, name_span }
+            }
+        }
+        Err(_) if chunk.fragment().starts_with("<<") => ErrorKind::UnclosedInlineTag {
+            open: span.start..span.start + 2,
+        },
+        Err(_) => ErrorKind::UnexpectedContent,
+    };
+    Ok((source, ErrorNode { span, kind }))
+}
+
+pub fn following_word_chars(source: Span) -> IResult<Span, Span> {
     // Any character (including "<") that's not
-    // a whitespace or a break
-    let (source, start) = position(source)?;
-    let (source, val) = is_not(" \n\t\r")(source)?;
-    let (source, end) = position(source)?;
+    // a whitespace or a break. Stops at "<<" so
+    // a tag can start right after a word
+    let (source, val) = recognize(many1(alt((is_not(" \n\t\r<"), single_lt))))(source)?;
+    Ok((source, val))
+}
+
+pub fn inline(source: Span) -> IResult<Span, Inline> {
+    let (source, response) = alt((map(inline_tag, Inline::Tag), map(word, Inline::Word)))(source)?;
+    Ok((source, response))
+}
+
+pub fn inline_tag(source: Span) -> IResult<Span, InlineTag> {
+    let (source, open) = tag("<<")(source)?;
+    let (source, first_param) = tag_param(source)?;
+    let (source, rest) = many0(pair(tag("|"), tag_param))(source)?;
+    let (source, close) = tag(">>")(source)?;
+    let mut params = vec![first_param];
+    let mut pipes = vec![];
+    rest.into_iter().for_each(|(pipe, param)| {
+        pipes.push(span_range(&pipe));
+        params.push(param);
+    });
     Ok((
         source,
-        vec![NomToken::String(
-            val.to_string(),
-            start.location_offset(),
-            end.location_offset(),
-        )],
+        InlineTag {
+            span: span_range(&open).start..span_range(&close).end,
+            params,
+            pipes,
+        },
     ))
 }
 
-pub fn initial_paragraph_word(source: Span) -> IResult<Span, Vec<NomToken>> {
+pub fn initial_paragraph_word(source: Span) -> IResult<Span, Word> {
     // get the first character of a word that
     // allows for a "<", but not two in a row
-    let (source, response) = alt((word_without_leading_dash, word_without_leading_dash))(source)?;
+    let (source, response) = word_without_leading_dash(source)?;
     Ok((source, response))
 }
 
-pub fn initial_word_chars(source: Span) -> IResult<Span, Vec<NomToken>> {
+pub fn initial_word_chars(source: Span) -> IResult<Span, Span> {
     // get the first character of a word that
     // allows for a "<", but not two in a row
     let (source, response) = alt((non_lt_char, lt_with_non_lt_char))(source)?;
     Ok((source, response))
 }
 
-pub fn key_value_attribute(source: Span) -> IResult<Span, Vec<NomToken>> {
-    // let (source, _) = multispace0(source)?;
-    // let (source, _) = multispace0(source)?;
-    let (source, mut response) = dashes(source)?;
-    let (source, key_start) = position(source)?;
-    let (source, key_value) = is_not(":\n")(source)?;
-    let (source, key_end) = position(source)?;
-    let key_attr = NomToken::Comment(
-        key_value.to_string(),
-        key_start.location_offset(),
-        key_end.location_offset(),
-    );
-    response.push(key_attr);
-    let (source, colon_start) = position(source)?;
-    let (source, colon_value) = tag(":")(source)?;
-    let (source, colon_end) = position(source)?;
-    let colon = NomToken::Comment(
-        colon_value.to_string(),
-        colon_start.location_offset(),
-        colon_end.location_offset(),
-    );
-    response.push(colon);
+pub fn key_value_attribute(source: Span) -> IResult<Span, Attribute> {
+    let (source, dashes) = dashes(source)?;
+    let (source, key) = is_not(":\r\n")(source)?;
+    let (source, colon) = tag(":")(source)?;
     let (source, _) = space0(source)?;
-    let (source, val_start) = position(source)?;
-    let (source, val_value) = is_not("\n")(source)?;
-    let (source, val_end) = position(source)?;
-    let val_attr = NomToken::Comment(
-        val_value.to_string(),
-        val_start.location_offset(),
-        val_end.location_offset(),
-    );
-    response.push(val_attr);
-    Ok((source, response))
+    // Trailing whitespace isn't part of the value.
+    // `-- key:` on its own has an empty value.
+    let (_, line) = opt(is_not("\r\n"))(source)?;
+    let length = line.map(|line| line.fragment().trim_end().len());
+    let (source, value) = take(length.unwrap_or_default())(source)?;
+    let (source, _) = space0(source)?;
+    Ok((
+        source,
+        Attribute {
+            span: dashes.start..span_range(&value).end,
+            dashes,
+            key: key.to_string(),
+            key_span: span_range(&key),
+            value: Some(AttributeValue {
+                colon: span_range(&colon),
+                text: value.to_string(),
+                span: span_range(&value),
+            }),
+        },
+    ))
 }
 
-pub fn list_item(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, start) = position(source)?;
-    let (source, name) = tag("-")(source)?;
-    let (source, end) = position(source)?;
-    let dash = NomToken::ListDash(
-        name.to_string(),
-        start.location_offset(),
-        end.location_offset(),
-    );
-    let (source, _) = space1(source)?;
-    let mut response = vec![dash];
-    let (source, mut paragraphs) = paragraphs(source)?;
-    response.append(&mut paragraphs);
-    Ok((source, response))
+pub fn line_continuation(source: Span) -> IResult<Span, ()> {
+    // A line break inside a paragraph. A blank line
+    // ends the paragraph and a line that starts with
+    // dashes is the next section header
+    let (source, _) = space0(source)?;
+    let (source, _) = single_newline(source)?;
+    let (source, _) = not(blank_line)(source)?;
+    let (source, _) = not(dashes)(source)?;
+    let (source, _) = space0(source)?;
+    Ok((source, ()))
 }
 
-pub fn list_type_section(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, mut response) = dashes(source)?;
-    let (source, start) = position(source)?;
-    let (source, name) = alt((tag("list"), tag("notes"), tag("warnings")))(source)?;
+pub fn list_item(source: Span) -> IResult<Span, ListItem> {
+    let (source, dash) = tag("-")(source)?;
+    let (source, _) = space1(source)?;
+    let (source, paragraphs) = paragraphs(source)?;
     let (source, end) = position(source)?;
-    response.push(NomToken::Class(
-        name.to_string(),
-        start.location_offset(),
-        end.location_offset(),
-    ));
+    Ok((
+        source,
+        ListItem {
+            span: span_range(&dash).start..end.location_offset(),
+            dash: span_range(&dash),
+            paragraphs,
+        },
+    ))
+}
+
+pub fn list_type_section(source: Span) -> IResult<Span, Section> {
+    let (source, dashes) = dashes(source)?;
+    let (source, name) = section_name(source)?;
     let (source, _) = space0(source)?;
-    let (source, _) = newline(source)?;
-    let (source, attrs) = opt(separated_list0(tag("\n"), attribute))(source)?;
-    if let Some(attrs) = attrs {
-        response.append(&mut attrs.into_iter().flatten().collect::<Vec<NomToken>>());
-    }
-    let (source, _) = empty_line(source)?;
+    let (source, _) = line_ending(source)?;
+    let (source, attrs) = opt(attributes)(source)?;
+    let (source, _) = blank_line(source)?;
     let (source, list_items) = opt(separated_list0(empty_line, list_item))(source)?;
-    if let Some(list_items) = list_items {
-        response.append(&mut list_items.into_iter().flatten().collect::<Vec<NomToken>>());
-    }
-    Ok((source, response))
+    let (source, end) = position(source)?;
+    Ok((
+        source,
+        Section {
+            span: dashes.start..end.location_offset(),
+            dashes,
+            name: name.to_string(),
+            name_span: span_range(&name),
+            attributes: attrs.unwrap_or_default(),
+            body: SectionBody::List(list_items.unwrap_or_default()),
+            block: None,
+        },
+    ))
 }
 
-pub fn lt_with_non_lt_char(source: Span) -> IResult<Span, Vec<NomToken>> {
+pub fn lt_with_non_lt_char(source: Span) -> IResult<Span, Span> {
     // A less than with a trailing non less than
     // character
-    let (source, start1) = position(source)?;
-    let (source, val1) = tag("<")(source)?;
-    let (source, end1) = position(source)?;
-    let mut response = vec![NomToken::String(
-        val1.to_string(),
-        start1.location_offset(),
-        end1.location_offset(),
-    )];
-    let (source, mut second_char) = non_lt_char(source)?;
-    response.append(&mut second_char);
+    let (source, response) = recognize(pair(tag("<"), non_lt_char))(source)?;
     Ok((source, response))
 }
 
-pub fn metadata_type_section(source: Span) -> IResult<Span, Vec<NomToken>> {
-    // dbg!("EEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEE");
-    // dbg!(source);
-    let (source, mut response) = dashes(source)?;
-    let (source, start) = position(source)?;
-    let (source, name) = alt((tag("metadata"), tag("categories"), tag("group")))(source)?;
-    let (source, end) = position(source)?;
-    response.push(NomToken::Class(
-        name.to_string(),
-        start.location_offset(),
-        end.location_offset(),
-    ));
+pub fn metadata_type_section(source: Span) -> IResult<Span, Section> {
+    let (source, dashes) = dashes(source)?;
+    let (source, name) = section_name(source)?;
     let (source, _) = blank_line(source)?;
-
-    // let (source, _) = space0(source)?;
-    // let (source, _) = newline(source)?;
-    // let (source, attrs) = opt(separated_list0(tag("\n"), attribute))(source)?;
     let (source, attrs) = opt(attributes)(source)?;
-    if let Some(mut attrs) = attrs {
-        // response.append(&mut attrs.into_iter().flatten().collect::<Vec<NomToken>>());
-        response.append(&mut attrs);
-    }
-
     let (source, _) = opt(blank_line)(source)?;
-    // dbg!("DDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDDD");
-    // dbg!(source);
-    Ok((source, response))
-}
-
-pub fn nom_parse(text: &str) -> IResult<Span, Vec<NomToken>> {
-    let source = Span::new(text);
-    let (source, response) = many1(section)(source)?;
+    let attributes = attrs.unwrap_or_default();
+    // The attributes eat their own line endings so the
+    // section ends where the last one does
+    let end = attributes
+        .last()
+        .map(|attr| attr.span.end)
+        .unwrap_or(span_range(&name).end);
     Ok((
         source,
-        response.into_iter().flatten().collect::<Vec<NomToken>>(),
+        Section {
+            span: dashes.start..end,
+            dashes,
+            name: name.to_string(),
+            name_span: span_range(&name),
+            attributes,
+            body: SectionBody::Empty,
+            block: None,
+        },
     ))
 }
 
-pub fn non_lt_char(source: Span) -> IResult<Span, Vec<NomToken>> {
+pub fn nom_parse<'a>(text: &'a str, registry: &SectionRegistry) -> IResult<Span<'a>, Document> {
+    let mut errors = vec![];
+    let (source, (sections, _)) = sections(Span::new(text), registry, None, &mut errors)?;
+    errors.sort_by_key(|error| error.span.start);
+    Ok((source, Document { sections, errors }))
+}
+
+fn missing_blank_line(after_header: &str) -> bool {
+    // Skip the rest of the header line and any
+    // attributes then see if the next line has
+    // content on it
+    after_header
+        .split('\n')
+        .skip(1)
+        .find(|line| !line.starts_with("-- "))
+        .map(|line| !line.trim().is_empty())
+        .unwrap_or(false)
+}
+
+pub fn non_lt_char(source: Span) -> IResult<Span, Span> {
     // Any character (including "<") that's not
     // a whitespace or a break
-    let (source, start) = position(source)?;
-    let (source, val) = none_of("< \n\t\r")(source)?;
-    let (source, end) = position(source)?;
-    Ok((
-        source,
-        vec![NomToken::String(
-            val.to_string(),
-            start.location_offset(),
-            end.location_offset(),
-        )],
-    ))
+    let (source, val) = recognize(none_of("< \n\t\r"))(source)?;
+    Ok((source, val))
 }
 
-pub fn paragraph(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, mut response) = initial_paragraph_word(source)?;
-    let (source, _) = space0(source)?;
-    let (source, items) = opt(separated_list1(wordbreak, word))(source)?;
-    if let Some(items) = items {
-        response.append(&mut items.into_iter().flatten().collect::<Vec<NomToken>>());
-    }
-    // items.into_iter().for_each(|mut i| response.append(&mut i));
-    //response.append(&mut items.iter_mut().flatten().collect::<Vec<NomToken>>());
-    // let mut collected_items = items.into_iter().flatten().collect::<Vec<NomToken>>();
-    // response.append(&mut collected_items);
+pub fn paragraph(source: Span) -> IResult<Span, Paragraph> {
+    let (source, first) = alt((
+        map(inline_tag, Inline::Tag),
+        map(initial_paragraph_word, Inline::Word),
+    ))(source)?;
+    let (source, mut rest) = many0(preceded(opt(wordbreak), inline))(source)?;
+    let mut content = vec![first];
+    content.append(&mut rest);
     Ok((
-        source, // items.into_iter().flatten().collect::<Vec<NomToken>>(),
-        response,
+        source,
+        Paragraph {
+            span: content[0].span().start..content[content.len() - 1].span().end,
+            content,
+        },
     ))
 }
 
-pub fn paragraph_type_section(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, mut response) = dashes(source)?;
-    let (source, start) = position(source)?;
-    let (source, name) = alt((
-        tag("aside"),
-        tag("blockquote"),
-        tag("bookmark"),
-        tag("footnote"),
-        tag("h1"),
-        tag("h2"),
-        tag("h3"),
-        tag("h4"),
-        tag("h5"),
-        tag("h6"),
-        tag("hr"),
-        tag("image"),
-        tag("note"),
-        tag("p"),
-        tag("reference"),
-        tag("subtitle"),
-        tag("title"),
-        tag("vimeo"),
-        tag("warning"),
-        tag("youtube"),
-    ))(source)?;
-    let (source, end) = position(source)?;
-    response.push(NomToken::Class(
-        name.to_string(),
-        start.location_offset(),
-        end.location_offset(),
-    ));
+pub fn paragraph_type_section(source: Span, body: BodyPresence) -> IResult<Span, Section> {
+    let (source, dashes) = dashes(source)?;
+    let (source, name) = section_name(source)?;
     let (source, _) = space0(source)?;
-    let (source, _) = newline(source)?;
+    let (source, _) = alt((value((), line_ending), value((), eof)))(source)?;
     let (source, attrs) = opt(attributes)(source)?;
-    if let Some(mut attrs) = attrs {
-        // response.append(&mut attrs.into_iter().flatten().collect::<Vec<NomToken>>());
-        response.append(&mut attrs);
+    let (source, paragraphs) = match body {
+        BodyPresence::Required => {
+            let (source, paragraphs) = preceded(blank_line, paragraphs)(source)?;
+            (source, Some(paragraphs))
+        }
+        BodyPresence::Optional => opt(preceded(blank_line, paragraphs))(source)?,
+        BodyPresence::None => (source, None),
+    };
+    // Without a body the header still needs a blank
+    // line or the end after it. Content that's there
+    // anyway makes this fail and become an error node.
+    if paragraphs.is_none() {
+        peek(alt((blank_line, value((), eof))))(source)?;
+        not(preceded(many0(blank_line), paragraph))(source)?;
     }
-    let (source, _) = blank_line(source)?;
-    let (source, mut paragraphs) = paragraphs(source)?;
-    response.append(&mut paragraphs);
-    Ok((source, response))
+    let body = paragraphs
+        .map(SectionBody::Paragraphs)
+        .unwrap_or(SectionBody::Empty);
+    let end = body_end(&body)
+        .or(attrs.as_ref().and_then(|a| a.last()).map(|a| a.span.end))
+        .unwrap_or(span_range(&name).end);
+    Ok((
+        source,
+        Section {
+            span: dashes.start..end,
+            dashes,
+            name: name.to_string(),
+            name_span: span_range(&name),
+            attributes: attrs.unwrap_or_default(),
+            body,
+            block: None,
+        },
+    ))
 }
 
-pub fn paragraphs(source: Span) -> IResult<Span, Vec<NomToken>> {
+pub fn paragraphs(source: Span) -> IResult<Span, Vec<Paragraph>> {
     let (source, items) = separated_list1(empty_line, paragraph)(source)?;
+    Ok((source, items))
+}
+
+// Takes every line up to the first one `stop`
+// matches, byte for byte. Trailing blank lines are
+// left in the input.
+pub fn raw_body(source: Span, stop: impl Fn(&str) -> bool) -> IResult<Span, Option<RawBody>> {
+    let text = source.fragment();
+    let mut length = 0;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if stop(line.trim_end()) {
+            break;
+        }
+        if !line.trim().is_empty() {
+            length = offset + line.trim_end_matches(['\r', '\n']).len();
+        }
+        offset += line.len();
+    }
+    if length == 0 {
+        return Ok((source, None));
+    }
+    let (source, raw) = take(length)(source)?;
     Ok((
         source,
-        items.into_iter().flatten().collect::<Vec<NomToken>>(),
+        Some(RawBody {
+            span: span_range(&raw),
+            text: raw.to_string(),
+        }),
     ))
 }
 
-pub fn section(source: Span) -> IResult<Span, Vec<NomToken>> {
-    // dbg!("EEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEEE");
-    // dbg!(source);
-    let (source, response) = alt((
-        paragraph_type_section,
-        metadata_type_section,
-        code_type_section,
-        list_type_section,
-    ))(source)?;
+// Whether a line starts a section or closes a
+// block. Lines like `-- a comment` that aren't
+// registered sections stay part of raw bodies.
+// Keys with whitespace in them are left out of the
+// section and reported on their own so the rest of
+// the section still parses
+fn invalid_attributes(section: &mut Section, errors: &mut Vec<ErrorNode>) {
+    section.attributes.retain(|attr| {
+        if !attr.key.contains(char::is_whitespace) {
+            return true;
+        }
+        errors.push(ErrorNode {
+            span: attr.span.clone(),
+            kind: ErrorKind::InvalidAttribute {
+                key: attr.key.clone(),
+                key_span: attr.key_span.clone(),
+            },
+        });
+        false
+    });
+}
+
+pub fn is_section_line(line: &str, registry: &SectionRegistry) -> bool {
+    match line.strip_prefix("-- ") {
+        Some(name) => {
+            let name = name.trim_end();
+            name.starts_with('/') || registry.get(name.trim_end_matches('/')).is_some()
+        }
+        None => false,
+    }
+}
+
+fn resync_offset(text: &str, registry: &SectionRegistry) -> usize {
+    // The next place a section could start. That's a
+    // "-- " line after a blank line or a line that's
+    // nothing but a known section name. The first line
+    // is always included so the parser moves forward
+    let mut offset = 0;
+    let mut previous_blank = false;
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        let name = line.get(3..).unwrap_or_default().trim_end();
+        if index > 0
+            && line.starts_with("-- ")
+            && (previous_blank
+                || name.starts_with('/')
+                || registry.get(name.trim_end_matches('/')).is_some())
+        {
+            return offset;
+        }
+        previous_blank = line.trim().is_empty();
+        offset += line.len();
+    }
+    text.len()
+}
+
+pub fn sections<'a>(
+    source: Span<'a>,
+    registry: &SectionRegistry,
+    block_name: Option<&str>,
+    errors: &mut Vec<ErrorNode>,
+) -> IResult<Span<'a>, (Vec<Section>, Option<BlockEnd>)> {
+    // Sections are parsed one at a time. Anything that
+    // doesn't parse becomes an error node running up
+    // to the next section header so the rest of the
+    // document still comes through. Inside a block this
+    // stops at the first end marker.
+    let mut source = source;
+    let mut response = vec![];
+    loop {
+        let (rest, _) = multispace0(source)?;
+        source = rest;
+        if source.fragment().is_empty() {
+            return Ok((source, (response, None)));
+        }
+        if let Ok((rest, end)) = block_end(source) {
+            if block_name.is_some() {
+                return Ok((rest, (response, Some(end))));
+            }
+            errors.push(ErrorNode {
+                span: end.span.clone(),
+                kind: ErrorKind::UnexpectedBlockEnd {
+                    name: end.name,
+                    name_span: end.name_span,
+                },
+            });
+            source = rest;
+            continue;
+        }
+        let parsed = match block_start(source) {
+            Ok(_) => block_section(source, registry, errors),
+            Err(_) => section(source, registry),
+        };
+        match parsed {
+            Ok((rest, mut section)) => {
+                invalid_attributes(&mut section, errors);
+                response.push(section);
+                source = rest;
+            }
+            Err(_) => {
+                let (rest, error) = error_node(source, registry)?;
+                errors.push(error);
+                source = rest;
+            }
+        }
+    }
+}
+
+pub fn section<'a>(source: Span<'a>, registry: &SectionRegistry) -> IResult<Span<'a>, Section> {
+    // The registry decides which kind of body
+    // the section has. Names it doesn't know
+    // about fail here and become error nodes
+    let (_, name) = section_header(source)?;
+    let spec = registry
+        .get(name.fragment())
+        .ok_or_else(|| nom::Err::Error(nom::error::Error::new(name, nom::error::ErrorKind::Tag)))?;
+    let (source, response) = match spec.kind {
+        BodyKind::Paragraph => paragraph_type_section(source, spec.body)?,
+        BodyKind::List => list_type_section(source)?,
+        BodyKind::Raw => code_type_section(source, registry)?,
+        BodyKind::Metadata => metadata_type_section(source)?,
+    };
     let (source, _) = opt(empty_line)(source)?;
     Ok((source, response))
 }
 
-pub fn single_character_word(source: Span) -> IResult<Span, Vec<NomToken>> {
+pub fn section_name(source: Span) -> IResult<Span, Span> {
+    let (source, name) = is_not(" \t\r\n")(source)?;
+    Ok((source, name))
+}
+
+pub fn section_header(source: Span) -> IResult<Span, Span> {
+    let (source, _) = dashes(source)?;
+    let (source, name) = section_name(source)?;
+    Ok((source, name))
+}
+
+pub fn single_character_word(source: Span) -> IResult<Span, Word> {
+    let (source, _) = not(tag("<<"))(source)?;
+    let (source, the_char) = recognize(none_of(" \r\n"))(source)?;
+    Ok((source, span_word(&the_char)))
+}
+
+fn single_lt(source: Span) -> IResult<Span, Span> {
+    let (source, response) = terminated(tag("<"), not(tag("<")))(source)?;
+    Ok((source, response))
+}
+
+pub fn single_newline(source: Span) -> IResult<Span, ()> {
+    let (source, _) = line_ending(source)?;
+    Ok((source, ()))
+}
+
+fn span_range(span: &Span) -> Range<usize> {
+    span.location_offset()..span.location_offset() + span.fragment().len()
+}
+
+pub fn tag_param(source: Span) -> IResult<Span, TagParam> {
+    let (source, _) = opt(wordbreak)(source)?;
     let (source, start) = position(source)?;
-    let (source, the_char) = none_of(" \n")(source)?;
-    let (source, end) = position(source)?;
-    let response = vec![NomToken::String(
-        the_char.to_string(),
-        start.location_offset(),
-        end.location_offset(),
-    )];
+    let (source, content) = many0(terminated(tag_inline, opt(wordbreak)))(source)?;
+    let span = match (content.first(), content.last()) {
+        (Some(first), Some(last)) => first.span().start..last.span().end,
+        _ => start.location_offset()..start.location_offset(),
+    };
+    Ok((source, TagParam { span, content }))
+}
+
+fn tag_inline(source: Span) -> IResult<Span, Inline> {
+    let (source, response) =
+        alt((map(inline_tag, Inline::Tag), map(tag_word, Inline::Word)))(source)?;
     Ok((source, response))
 }
 
-pub fn single_newline(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, _) = tag("\n")(source)?;
-    Ok((source, vec![NomToken::Whitespace]))
+pub fn tag_word(source: Span) -> IResult<Span, Word> {
+    // Words inside a tag also stop at pipes
+    // and at the closing ">>"
+    let (source, response) = recognize(many1(alt((
+        is_not(" \n\t\r|<>"),
+        single_lt,
+        terminated(tag(">"), not(tag(">"))),
+    ))))(source)?;
+    Ok((source, span_word(&response)))
+}
+
+fn span_word(span: &Span) -> Word {
+    Word {
+        text: span.fragment().to_string(),
+        span: span_range(span),
+    }
 }
 
-pub fn whitespace(source: Span) -> IResult<Span, Vec<NomToken>> {
+pub fn whitespace(source: Span) -> IResult<Span, ()> {
     let (source, _) = is_a(" \t")(source)?;
-    Ok((source, vec![NomToken::Whitespace]))
+    Ok((source, ()))
 }
 
-pub fn word(source: Span) -> IResult<Span, Vec<NomToken>> {
+pub fn word(source: Span) -> IResult<Span, Word> {
     let (source, response) = alt((word_base, single_character_word))(source)?;
     Ok((source, response))
 }
 
-pub fn word_base(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, mut response) = initial_word_chars(source)?;
-    let (source, mut part_two) = following_word_chars(source)?;
-    response.append(&mut part_two);
-    Ok((source, response))
+pub fn word_base(source: Span) -> IResult<Span, Word> {
+    let (source, response) = recognize(pair(initial_word_chars, following_word_chars))(source)?;
+    Ok((source, span_word(&response)))
 }
 
-pub fn word_without_leading_dash(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, start) = position(source)?;
-    let (source, part_one) = none_of("-")(source)?;
-    let (source, end) = position(source)?;
-    let mut response = vec![NomToken::String(
-        part_one.to_string(),
-        start.location_offset(),
-        end.location_offset(),
-    )];
-    let (source, mut part_two) = following_word_chars(source)?;
-    response.append(&mut part_two);
-    Ok((source, response))
+pub fn word_without_leading_dash(source: Span) -> IResult<Span, Word> {
+    let (source, _) = not(tag("<<"))(source)?;
+    let (source, response) =
+        recognize(pair(none_of("- \n\t\r"), opt(following_word_chars)))(source)?;
+    Ok((source, span_word(&response)))
 }
 
-pub fn wordbreak(source: Span) -> IResult<Span, Vec<NomToken>> {
-    let (source, _) = alt((single_newline, whitespace))(source)?;
-    Ok((source, vec![NomToken::Whitespace]))
+pub fn wordbreak(source: Span) -> IResult<Span, ()> {
+    let (source, _) = alt((line_continuation, whitespace))(source)?;
+    Ok((source, ()))
 }
 
 #[cfg(test)]
-
 mod test {
     use super::*;
     use pretty_assertions::{assert_eq, assert_ne};
 
+    fn parse(source: &str) -> Document {
+        nom_parse(source, &SectionRegistry::default()).unwrap().1
+    }
+
+    fn word_at(text: &str, start: usize) -> Word {
+        Word {
+            text: text.to_string(),
+            span: start..start + text.len(),
+        }
+    }
+
+    fn w(text: &str, start: usize) -> Inline {
+        Inline::Word(word_at(text, start))
+    }
+
     #[test]
     pub fn test_attributes() {
         let source = Span::new("-- sierra\n-- bravo\n");
         let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Comment("sierra".to_string(), 3, 9),
-            NomToken::Decorator("--".to_string(), 10, 12),
-            NomToken::Comment("bravo".to_string(), 13, 18),
+            Attribute {
+                span: 0..9,
+                dashes: 0..2,
+                key: "sierra".to_string(),
+                key_span: 3..9,
+                value: None,
+            },
+            Attribute {
+                span: 10..18,
+                dashes: 10..12,
+                key: "bravo".to_string(),
+                key_span: 13..18,
+                value: None,
+            },
         ];
         let right = attributes(source).unwrap().1;
         assert_eq!(left, right);
@@ -492,10 +806,13 @@ mod test {
     #[test]
     pub fn test_boolean_attribute() {
         let source = Span::new("-- sierra\n");
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Comment("sierra".to_string(), 3, 9),
-        ];
+        let left = Attribute {
+            span: 0..9,
+            dashes: 0..2,
+            key: "sierra".to_string(),
+            key_span: 3..9,
+            value: None,
+        };
         let right = boolean_attribute(source).unwrap().1;
         assert_eq!(left, right);
     }
@@ -503,7 +820,7 @@ mod test {
     #[test]
     pub fn test_dashes() {
         let source = Span::new("-- ");
-        let left = vec![NomToken::Decorator("--".to_string(), 0, 2)];
+        let left = 0..2;
         let right = dashes(source).unwrap().1;
         assert_eq!(left, right);
     }
@@ -511,34 +828,31 @@ mod test {
     #[test]
     pub fn test_empty_line() {
         let source = Span::new("\n\n");
-        let left = vec![NomToken::Whitespace];
-        let right = empty_line(source).unwrap().1;
-        assert_eq!(left, right);
+        let left = "";
+        let right = empty_line(source).unwrap().0;
+        assert_eq!(left.to_string(), right.fragment().to_string());
     }
 
     #[test]
     pub fn test_empty_line_including_whitespace() {
-        let source = Span::new("\n  \n\n\n \n");
-        let left = vec![NomToken::Whitespace];
-        let right = empty_line(source).unwrap().1;
-        assert_eq!(left, right);
+        let source = Span::new("\n  \n\n\n \nx");
+        let left = "x";
+        let right = empty_line(source).unwrap().0;
+        assert_eq!(left.to_string(), right.fragment().to_string());
     }
 
     #[test]
     pub fn test_following_word_chars_test() {
         let source = Span::new("lfa");
-        let left = vec![NomToken::String("lfa".to_string(), 0, 3)];
+        let left = "lfa";
         let right = following_word_chars(source).unwrap().1;
-        assert_eq!(left, right);
+        assert_eq!(left.to_string(), right.fragment().to_string());
     }
 
     #[test]
     pub fn test_initial_paragraph_word_via_just_text() {
         let source = Span::new("alfa ");
-        let left = vec![
-            NomToken::String("a".to_string(), 0, 1),
-            NomToken::String("lfa".to_string(), 1, 4),
-        ];
+        let left = word_at("alfa", 0);
         let right = initial_paragraph_word(source).unwrap().1;
         assert_eq!(left, right);
     }
@@ -546,37 +860,90 @@ mod test {
     #[test]
     pub fn test_initial_word_chars_via_lt() {
         let source = Span::new("<f");
-        let left = vec![
-            NomToken::String("<".to_string(), 0, 1),
-            NomToken::String("f".to_string(), 1, 2),
-        ];
+        let left = "<f";
         let right = initial_word_chars(source).unwrap().1;
-        assert_eq!(left, right);
+        assert_eq!(left.to_string(), right.fragment().to_string());
     }
 
     #[test]
     pub fn test_key_value_attribute() {
         let source = Span::new("-- alfa: bravo");
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Comment("alfa".to_string(), 3, 7),
-            NomToken::Comment(":".to_string(), 7, 8),
-            NomToken::Comment("bravo".to_string(), 9, 14),
-        ];
+        let left = Attribute {
+            span: 0..14,
+            dashes: 0..2,
+            key: "alfa".to_string(),
+            key_span: 3..7,
+            value: Some(AttributeValue {
+                colon: 7..8,
+                text: "bravo".to_string(),
+                span: 9..14,
+            }),
+        };
         let right = key_value_attribute(source).unwrap().1;
         assert_eq!(left, right);
     }
 
+    #[test]
+    pub fn test_key_value_attribute_trailing_whitespace() {
+        let document = parse("-- metadata\n-- id: abcd \t\n-- date: 2023-09-28");
+        let id = document.metadata_id().unwrap();
+        assert_eq!("abcd".to_string(), id.text);
+        assert_eq!(19..23, id.span);
+        assert_eq!(
+            "2023-09-28".to_string(),
+            document.sections[0]
+                .attribute("date")
+                .unwrap()
+                .value
+                .as_ref()
+                .unwrap()
+                .text
+        );
+    }
+
+    #[test]
+    pub fn test_attribute_key_with_whitespace() {
+        let document = parse("-- image\n-- src a.jpg\n-- autoplay \n\nhello");
+        assert_eq!(
+            vec![ErrorNode {
+                span: 9..21,
+                kind: ErrorKind::InvalidAttribute {
+                    key: "src a.jpg".to_string(),
+                    key_span: 12..21,
+                },
+            }],
+            document.errors
+        );
+        let section = &document.sections[0];
+        assert_eq!(1, section.attributes.len());
+        assert_eq!("autoplay", section.attributes[0].key);
+    }
+
+    #[test]
+    pub fn test_key_value_attribute_empty_value() {
+        let document = parse("-- p\n-- class:\n\nhello");
+        assert!(document.errors.is_empty());
+        let value = document.sections[0]
+            .attribute("class")
+            .unwrap()
+            .value
+            .clone()
+            .unwrap();
+        assert_eq!("", value.text);
+        assert_eq!(14..14, value.span);
+    }
+
     #[test]
     pub fn test_list_item() {
         let source = Span::new("- papa sierra");
-        let left = vec![
-            NomToken::ListDash("-".to_string(), 0, 1),
-            NomToken::String("p".to_string(), 2, 3),
-            NomToken::String("apa".to_string(), 3, 6),
-            NomToken::String("s".to_string(), 7, 8),
-            NomToken::String("ierra".to_string(), 8, 13),
-        ];
+        let left = ListItem {
+            span: 0..13,
+            dash: 0..1,
+            paragraphs: vec![Paragraph {
+                span: 2..13,
+                content: vec![w("papa", 2), w("sierra", 7)],
+            }],
+        };
         let right = list_item(source).unwrap().1;
         assert_eq!(left, right);
     }
@@ -586,22 +953,31 @@ mod test {
         // this is text, the span is created in the parser
         // for ease of use
         let source = "-- title\n\nsierra";
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Class("title".to_string(), 3, 8),
-            NomToken::String("s".to_string(), 10, 11),
-            NomToken::String("ierra".to_string(), 11, 16),
-        ];
-        let right = nom_parse(source).unwrap().1;
+        let left = Document {
+            sections: vec![Section {
+                span: 0..16,
+                dashes: 0..2,
+                name: "title".to_string(),
+                name_span: 3..8,
+                attributes: vec![],
+                body: SectionBody::Paragraphs(vec![Paragraph {
+                    span: 10..16,
+                    content: vec![w("sierra", 10)],
+                }]),
+                block: None,
+            }],
+            errors: vec![],
+        };
+        let right = parse(source);
         assert_eq!(left, right);
     }
 
     #[test]
     pub fn test_non_less_than_char_test() {
         let source = Span::new("a");
-        let left = vec![NomToken::String("a".to_string(), 0, 1)];
+        let left = "a";
         let right = non_lt_char(source).unwrap().1;
-        assert_eq!(left, right);
+        assert_eq!(left.to_string(), right.fragment().to_string());
     }
 
     #[test]
@@ -620,26 +996,18 @@ mod test {
     #[test]
     pub fn test_lt_with_non_lt_chars() {
         let source = Span::new("<a");
-        let left = vec![
-            NomToken::String("<".to_string(), 0, 1),
-            NomToken::String("a".to_string(), 1, 2),
-        ];
+        let left = "<a";
         let right = lt_with_non_lt_char(source).unwrap().1;
-        assert_eq!(left, right);
+        assert_eq!(left.to_string(), right.fragment().to_string());
     }
 
     #[test]
     pub fn test_paragraph() {
         let source = Span::new("echo <foxtrot hotel");
-        let left = vec![
-            NomToken::String("e".to_string(), 0, 1),
-            NomToken::String("cho".to_string(), 1, 4),
-            NomToken::String("<".to_string(), 5, 6),
-            NomToken::String("f".to_string(), 6, 7),
-            NomToken::String("oxtrot".to_string(), 7, 13),
-            NomToken::String("h".to_string(), 14, 15),
-            NomToken::String("otel".to_string(), 15, 19),
-        ];
+        let left = Paragraph {
+            span: 0..19,
+            content: vec![w("echo", 0), w("<foxtrot", 5), w("hotel", 14)],
+        };
         let right = paragraph(source).unwrap().1;
         assert_eq!(left, right);
     }
@@ -648,12 +1016,18 @@ mod test {
     pub fn test_paragraphs() {
         let source = Span::new("alfa\n\nbravo\n\ncharlie");
         let left = vec![
-            NomToken::String("a".to_string(), 0, 1),
-            NomToken::String("lfa".to_string(), 1, 4),
-            NomToken::String("b".to_string(), 6, 7),
-            NomToken::String("ravo".to_string(), 7, 11),
-            NomToken::String("c".to_string(), 13, 14),
-            NomToken::String("harlie".to_string(), 14, 20),
+            Paragraph {
+                span: 0..4,
+                content: vec![w("alfa", 0)],
+            },
+            Paragraph {
+                span: 6..11,
+                content: vec![w("bravo", 6)],
+            },
+            Paragraph {
+                span: 13..20,
+                content: vec![w("charlie", 13)],
+            },
         ];
         let right = paragraphs(source).unwrap().1;
         assert_eq!(left, right);
@@ -662,7 +1036,7 @@ mod test {
     #[test]
     pub fn test_single_character_word() {
         let source = Span::new("<");
-        let left = vec![NomToken::String("<".to_string(), 0, 1)];
+        let left = word_at("<", 0);
         let right = single_character_word(source).unwrap().1;
         assert_eq!(left, right);
     }
@@ -670,27 +1044,23 @@ mod test {
     #[test]
     pub fn test_single_newline() {
         let source = Span::new("\n");
-        let left = vec![NomToken::Whitespace];
-        let right = single_newline(source).unwrap().1;
-        assert_eq!(left, right);
+        let left = "";
+        let right = single_newline(source).unwrap().0;
+        assert_eq!(left.to_string(), right.fragment().to_string());
     }
 
     #[test]
     pub fn test_whitespace() {
-        let source = Span::new("  ");
-        let left = vec![NomToken::Whitespace];
-        let right = whitespace(source).unwrap().1;
-        assert_eq!(left, right);
+        let source = Span::new("  x");
+        let left = "x";
+        let right = whitespace(source).unwrap().0;
+        assert_eq!(left.to_string(), right.fragment().to_string());
     }
 
     #[test]
     pub fn test_word_via_lt() {
         let source = Span::new("<delta");
-        let left = vec![
-            NomToken::String("<".to_string(), 0, 1),
-            NomToken::String("d".to_string(), 1, 2),
-            NomToken::String("elta".to_string(), 2, 6),
-        ];
+        let left = word_at("<delta", 0);
         let right = word(source).unwrap().1;
         assert_eq!(left, right);
     }
@@ -698,10 +1068,7 @@ mod test {
     #[test]
     pub fn test_word_without_leading_dash() {
         let source = Span::new("<delta");
-        let left = vec![
-            NomToken::String("<".to_string(), 0, 1),
-            NomToken::String("delta".to_string(), 1, 6),
-        ];
+        let left = word_at("<delta", 0);
         let right = word_without_leading_dash(source).unwrap().1;
         assert_eq!(left, right);
     }
@@ -709,9 +1076,9 @@ mod test {
     #[test]
     pub fn test_wordbreak() {
         let source = Span::new("\n");
-        let left = vec![NomToken::Whitespace];
-        let right = wordbreak(source).unwrap().1;
-        assert_eq!(left, right);
+        let left = "";
+        let right = wordbreak(source).unwrap().0;
+        assert_eq!(left.to_string(), right.fragment().to_string());
     }
 
     // SECTION TYPES
@@ -720,13 +1087,21 @@ mod test {
     #[test]
     pub fn test_paragraph_type_section() {
         let source = Span::new("-- h1\n\nAlfa");
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Class("h1".to_string(), 3, 5),
-            NomToken::String("A".to_string(), 7, 8),
-            NomToken::String("lfa".to_string(), 8, 11),
-        ];
-        let right = paragraph_type_section(source).unwrap().1;
+        let left = Section {
+            span: 0..11,
+            dashes: 0..2,
+            name: "h1".to_string(),
+            name_span: 3..5,
+            attributes: vec![],
+            body: SectionBody::Paragraphs(vec![Paragraph {
+                span: 7..11,
+                content: vec![w("Alfa", 7)],
+            }]),
+            block: None,
+        };
+        let right = paragraph_type_section(source, BodyPresence::Required)
+            .unwrap()
+            .1;
         assert_eq!(left, right);
     }
 
@@ -736,28 +1111,75 @@ mod test {
     // #[ignore]
     pub fn test_code_section() {
         let source = Span::new("-- code\n-- b\n\nAlfa");
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Class("code".to_string(), 3, 7),
-            NomToken::Decorator("--".to_string(), 8, 10),
-            NomToken::Comment("b".to_string(), 11, 12),
-            NomToken::String("A".to_string(), 14, 15),
-            NomToken::String("lfa".to_string(), 15, 18),
-        ];
-        let right = code_type_section(source).unwrap().1;
+        let left = Section {
+            span: 0..18,
+            dashes: 0..2,
+            name: "code".to_string(),
+            name_span: 3..7,
+            attributes: vec![Attribute {
+                span: 8..12,
+                dashes: 8..10,
+                key: "b".to_string(),
+                key_span: 11..12,
+                value: None,
+            }],
+            body: SectionBody::Raw(RawBody {
+                span: 14..18,
+                text: "Alfa".to_string(),
+            }),
+            block: None,
+        };
+        let right = code_type_section(source, &SectionRegistry::default())
+            .unwrap()
+            .1;
         assert_eq!(left, right);
     }
 
+    #[test]
+    pub fn test_code_section_keeps_raw_text() {
+        let source = "-- code\n\nfn main() {\n    let x = 1;\n\n\n    -- not a section\n-- a comment\n}\n\n-- p\n\nalfa";
+        let document = parse(source);
+        assert_eq!(Vec::<ErrorNode>::new(), document.errors);
+        assert_eq!(2, document.sections.len());
+        let left = SectionBody::Raw(RawBody {
+            span: 9..73,
+            text: "fn main() {\n    let x = 1;\n\n\n    -- not a section\n-- a comment\n}"
+                .to_string(),
+        });
+        assert_eq!(left, document.sections[0].body);
+    }
+
+    #[test]
+    pub fn test_code_block_runs_to_end_marker() {
+        let source = "-- code/\n-- lang: neo\n\n  -- p\n\n  alfa\n\n-- /code";
+        let document = parse(source);
+        assert_eq!(Vec::<ErrorNode>::new(), document.errors);
+        let code = &document.sections[0];
+        let left = SectionBody::Raw(RawBody {
+            span: 23..37,
+            text: "  -- p\n\n  alfa".to_string(),
+        });
+        assert_eq!(left, code.body);
+        assert!(code.children().is_empty());
+        assert_eq!(0..47, code.span);
+    }
+
     #[test]
     pub fn test_section() {
         let source = Span::new("-- h1\n\nAlfa");
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Class("h1".to_string(), 3, 5),
-            NomToken::String("A".to_string(), 7, 8),
-            NomToken::String("lfa".to_string(), 8, 11),
-        ];
-        let right = section(source).unwrap().1;
+        let left = Section {
+            span: 0..11,
+            dashes: 0..2,
+            name: "h1".to_string(),
+            name_span: 3..5,
+            attributes: vec![],
+            body: SectionBody::Paragraphs(vec![Paragraph {
+                span: 7..11,
+                content: vec![w("Alfa", 7)],
+            }]),
+            block: None,
+        };
+        let right = section(source, &SectionRegistry::default()).unwrap().1;
         assert_eq!(left, right);
     }
 
@@ -765,15 +1187,28 @@ mod test {
     // #[ignore]
     pub fn test_list_type_section() {
         let source = Span::new("-- notes\n-- b\n\n- Alfa");
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Class("notes".to_string(), 3, 8),
-            NomToken::Decorator("--".to_string(), 9, 11),
-            NomToken::Comment("b".to_string(), 12, 13),
-            NomToken::ListDash("-".to_string(), 15, 16),
-            NomToken::String("A".to_string(), 17, 18),
-            NomToken::String("lfa".to_string(), 18, 21),
-        ];
+        let left = Section {
+            span: 0..21,
+            dashes: 0..2,
+            name: "notes".to_string(),
+            name_span: 3..8,
+            attributes: vec![Attribute {
+                span: 9..13,
+                dashes: 9..11,
+                key: "b".to_string(),
+                key_span: 12..13,
+                value: None,
+            }],
+            body: SectionBody::List(vec![ListItem {
+                span: 15..21,
+                dash: 15..16,
+                paragraphs: vec![Paragraph {
+                    span: 17..21,
+                    content: vec![w("Alfa", 17)],
+                }],
+            }]),
+            block: None,
+        };
         let right = list_type_section(source).unwrap().1;
         assert_eq!(left, right);
     }
@@ -781,14 +1216,25 @@ mod test {
     #[test]
     pub fn test_metadata_type_section_basic() {
         let source = Span::new("-- metadata\n-- id: asdf");
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Class("metadata".to_string(), 3, 11),
-            NomToken::Decorator("--".to_string(), 12, 14),
-            NomToken::Comment("id".to_string(), 15, 17),
-            NomToken::Comment(":".to_string(), 17, 18),
-            NomToken::Comment("asdf".to_string(), 19, 23),
-        ];
+        let left = Section {
+            span: 0..23,
+            dashes: 0..2,
+            name: "metadata".to_string(),
+            name_span: 3..11,
+            attributes: vec![Attribute {
+                span: 12..23,
+                dashes: 12..14,
+                key: "id".to_string(),
+                key_span: 15..17,
+                value: Some(AttributeValue {
+                    colon: 17..18,
+                    text: "asdf".to_string(),
+                    span: 19..23,
+                }),
+            }],
+            body: SectionBody::Empty,
+            block: None,
+        };
         let right = metadata_type_section(source).unwrap().1;
         assert_eq!(left, right);
     }
@@ -796,32 +1242,67 @@ mod test {
     #[test]
     pub fn test_metadata_type_sections_next_to_each_other() {
         let source = "-- categories\n-- Rust\n\n-- metadata\n-- id: tango";
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Class("categories".to_string(), 3, 13),
-            NomToken::Decorator("--".to_string(), 14, 16),
-            NomToken::Comment("Rust".to_string(), 17, 21),
-            NomToken::Decorator("--".to_string(), 23, 25),
-            NomToken::Class("metadata".to_string(), 26, 34),
-            NomToken::Decorator("--".to_string(), 35, 37),
-            NomToken::Comment("id".to_string(), 38, 40),
-            NomToken::Comment(":".to_string(), 40, 41),
-            NomToken::Comment("tango".to_string(), 42, 47),
-        ];
-        let right = nom_parse(source).unwrap().1;
+        let left = Document {
+            sections: vec![
+                Section {
+                    span: 0..21,
+                    dashes: 0..2,
+                    name: "categories".to_string(),
+                    name_span: 3..13,
+                    attributes: vec![Attribute {
+                        span: 14..21,
+                        dashes: 14..16,
+                        key: "Rust".to_string(),
+                        key_span: 17..21,
+                        value: None,
+                    }],
+                    body: SectionBody::Empty,
+                    block: None,
+                },
+                Section {
+                    span: 23..47,
+                    dashes: 23..25,
+                    name: "metadata".to_string(),
+                    name_span: 26..34,
+                    attributes: vec![Attribute {
+                        span: 35..47,
+                        dashes: 35..37,
+                        key: "id".to_string(),
+                        key_span: 38..40,
+                        value: Some(AttributeValue {
+                            colon: 40..41,
+                            text: "tango".to_string(),
+                            span: 42..47,
+                        }),
+                    }],
+                    body: SectionBody::Empty,
+                    block: None,
+                },
+            ],
+            errors: vec![],
+        };
+        let right = parse(source);
         assert_eq!(left, right);
     }
 
     #[test]
     pub fn test_h1_section() {
         let source = Span::new("-- h1\n\nAlfa");
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Class("h1".to_string(), 3, 5),
-            NomToken::String("A".to_string(), 7, 8),
-            NomToken::String("lfa".to_string(), 8, 11),
-        ];
-        let right = paragraph_type_section(source).unwrap().1;
+        let left = Section {
+            span: 0..11,
+            dashes: 0..2,
+            name: "h1".to_string(),
+            name_span: 3..5,
+            attributes: vec![],
+            body: SectionBody::Paragraphs(vec![Paragraph {
+                span: 7..11,
+                content: vec![w("Alfa", 7)],
+            }]),
+            block: None,
+        };
+        let right = paragraph_type_section(source, BodyPresence::Required)
+            .unwrap()
+            .1;
         assert_eq!(left, right);
     }
 
@@ -829,15 +1310,27 @@ mod test {
     // #[ignore]
     pub fn test_title_section() {
         let source = Span::new("-- title\n\nAlfa\n\nBravo");
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Class("title".to_string(), 3, 8),
-            NomToken::String("A".to_string(), 10, 11),
-            NomToken::String("lfa".to_string(), 11, 14),
-            NomToken::String("B".to_string(), 16, 17),
-            NomToken::String("ravo".to_string(), 17, 21),
-        ];
-        let right = paragraph_type_section(source).unwrap().1;
+        let left = Section {
+            span: 0..21,
+            dashes: 0..2,
+            name: "title".to_string(),
+            name_span: 3..8,
+            attributes: vec![],
+            body: SectionBody::Paragraphs(vec![
+                Paragraph {
+                    span: 10..14,
+                    content: vec![w("Alfa", 10)],
+                },
+                Paragraph {
+                    span: 16..21,
+                    content: vec![w("Bravo", 16)],
+                },
+            ]),
+            block: None,
+        };
+        let right = paragraph_type_section(source, BodyPresence::Required)
+            .unwrap()
+            .1;
         assert_eq!(left, right);
     }
 
@@ -845,21 +1338,46 @@ mod test {
     // #[ignore]
     pub fn test_title_with_key_value_attributes() {
         let source = Span::new("-- title\n-- autofocus\n-- delta: echo\n\nAlfa\n\nBravo");
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Class("title".to_string(), 3, 8),
-            NomToken::Decorator("--".to_string(), 9, 11),
-            NomToken::Comment("autofocus".to_string(), 12, 21),
-            NomToken::Decorator("--".to_string(), 22, 24),
-            NomToken::Comment("delta".to_string(), 25, 30),
-            NomToken::Comment(":".to_string(), 30, 31),
-            NomToken::Comment("echo".to_string(), 32, 36),
-            NomToken::String("A".to_string(), 38, 39),
-            NomToken::String("lfa".to_string(), 39, 42),
-            NomToken::String("B".to_string(), 44, 45),
-            NomToken::String("ravo".to_string(), 45, 49),
-        ];
-        let right = paragraph_type_section(source).unwrap().1;
+        let left = Section {
+            span: 0..49,
+            dashes: 0..2,
+            name: "title".to_string(),
+            name_span: 3..8,
+            attributes: vec![
+                Attribute {
+                    span: 9..21,
+                    dashes: 9..11,
+                    key: "autofocus".to_string(),
+                    key_span: 12..21,
+                    value: None,
+                },
+                Attribute {
+                    span: 22..36,
+                    dashes: 22..24,
+                    key: "delta".to_string(),
+                    key_span: 25..30,
+                    value: Some(AttributeValue {
+                        colon: 30..31,
+                        text: "echo".to_string(),
+                        span: 32..36,
+                    }),
+                },
+            ],
+            body: SectionBody::Paragraphs(vec![
+                Paragraph {
+                    span: 38..42,
+                    content: vec![w("Alfa", 38)],
+                },
+                Paragraph {
+                    span: 44..49,
+                    content: vec![w("Bravo", 44)],
+                },
+            ]),
+            block: None,
+        };
+        let right = paragraph_type_section(source, BodyPresence::Required)
+            .unwrap()
+            .1;
         assert_eq!(left, right);
     }
 
@@ -867,15 +1385,27 @@ mod test {
     // #[ignore]
     pub fn test_title_with_boolean_attributes() {
         let source = Span::new("-- title\n-- b\n\nAlfa");
-        let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Class("title".to_string(), 3, 8),
-            NomToken::Decorator("--".to_string(), 9, 11),
-            NomToken::Comment("b".to_string(), 12, 13),
-            NomToken::String("A".to_string(), 15, 16),
-            NomToken::String("lfa".to_string(), 16, 19),
-        ];
-        let right = paragraph_type_section(source).unwrap().1;
+        let left = Section {
+            span: 0..19,
+            dashes: 0..2,
+            name: "title".to_string(),
+            name_span: 3..8,
+            attributes: vec![Attribute {
+                span: 9..13,
+                dashes: 9..11,
+                key: "b".to_string(),
+                key_span: 12..13,
+                value: None,
+            }],
+            body: SectionBody::Paragraphs(vec![Paragraph {
+                span: 15..19,
+                content: vec![w("Alfa", 15)],
+            }]),
+            block: None,
+        };
+        let right = paragraph_type_section(source, BodyPresence::Required)
+            .unwrap()
+            .1;
         assert_eq!(left, right);
     }
 
@@ -883,21 +1413,401 @@ mod test {
     // #[ignore]
     pub fn test_integration_basic() {
         let source = "-- title\n\nAlfa\n\n-- h2\n\nBravo a b";
+        let left = vec![("title".to_string(), 0..14), ("h2".to_string(), 16..32)];
+        let right = parse(source)
+            .sections
+            .into_iter()
+            .map(|s| (s.name, s.span))
+            .collect::<Vec<_>>();
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    pub fn test_integration_words() {
+        let source = "-- title\n\nAlfa\n\n-- h2\n\nBravo a b";
+        let left = vec![w("Bravo", 23), w("a", 29), w("b", 31)];
+        let right = parse(source).sections[1].paragraphs()[0].content.clone();
+        assert_eq!(left, right);
+    }
+
+    // RECOVERY TESTS
+
+    #[test]
+    pub fn test_unknown_section_is_an_error_node() {
+        let source = "-- title\n\nAlfa\n\n-- titel\n\nBravo\n\n-- h2\n\nCharlie";
+        let document = parse(source);
+        let left = vec!["title".to_string(), "h2".to_string()];
+        let right = document
+            .sections
+            .iter()
+            .map(|s| s.name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(left, right);
+        let left = vec![ErrorNode {
+            span: 16..31,
+            kind: ErrorKind::UnknownSection {
+                name: "titel".to_string(),
+                name_span: 19..24,
+            },
+        }];
+        assert_eq!(left, document.errors);
+    }
+
+    #[test]
+    pub fn test_missing_blank_line_is_an_error_node() {
+        let source = "-- h2\nAlfa\n\n-- p\n\nBravo";
+        let document = parse(source);
+        let left = vec![ErrorNode {
+            span: 0..10,
+            kind: ErrorKind::MissingBlankLine {
+                name: "h2".to_string(),
+                name_span: 3..5,
+            },
+        }];
+        assert_eq!(left, document.errors);
+        assert_eq!("p".to_string(), document.sections[0].name);
+    }
+
+    #[test]
+    pub fn test_leading_content_is_an_error_node() {
+        let source = "Alfa\n\n-- p\n\nBravo";
+        let document = parse(source);
+        let left = vec![ErrorNode {
+            span: 0..4,
+            kind: ErrorKind::UnexpectedContent,
+        }];
+        assert_eq!(left, document.errors);
+        assert_eq!(1, document.sections.len());
+    }
+
+    #[test]
+    pub fn test_paragraph_stops_at_next_section() {
+        let source = "-- p\n\nAlfa\n-- h2\n\nBravo";
+        let document = parse(source);
+        assert_eq!(Vec::<ErrorNode>::new(), document.errors);
+        assert_eq!(0..10, document.sections[0].span);
+        assert_eq!("h2".to_string(), document.sections[1].name);
+    }
+
+    #[test]
+    pub fn test_section_without_body() {
+        let source = "-- hr\n\n-- p\n\nAlfa\n\n-- hr";
+        let document = parse(source);
+        assert_eq!(Vec::<ErrorNode>::new(), document.errors);
         let left = vec![
-            NomToken::Decorator("--".to_string(), 0, 2),
-            NomToken::Class("title".to_string(), 3, 8),
-            NomToken::String("A".to_string(), 10, 11),
-            NomToken::String("lfa".to_string(), 11, 14),
-            NomToken::Decorator("--".to_string(), 16, 18),
-            NomToken::Class("h2".to_string(), 19, 21),
-            NomToken::String("B".to_string(), 23, 24),
-            NomToken::String("ravo".to_string(), 24, 28),
-            NomToken::String("a".to_string(), 29, 30),
-            NomToken::String("b".to_string(), 31, 32),
+            ("hr".to_string(), 0..5, SectionBody::Empty),
+            (
+                "p".to_string(),
+                7..17,
+                SectionBody::Paragraphs(vec![Paragraph {
+                    span: 13..17,
+                    content: vec![w("Alfa", 13)],
+                }]),
+            ),
+            ("hr".to_string(), 19..24, SectionBody::Empty),
         ];
-        let right = nom_parse(source).unwrap().1;
+        let right = document
+            .sections
+            .into_iter()
+            .map(|s| (s.name, s.span, s.body))
+            .collect::<Vec<_>>();
         assert_eq!(left, right);
     }
 
-    //
+    #[test]
+    pub fn test_section_with_only_attributes() {
+        let source = "-- image\n-- src: /alfa.jpg\n-- alt: Bravo\n\n-- p\n\nCharlie";
+        let document = parse(source);
+        assert_eq!(Vec::<ErrorNode>::new(), document.errors);
+        let image = &document.sections[0];
+        assert_eq!(0..40, image.span);
+        assert_eq!(2, image.attributes.len());
+        assert_eq!(SectionBody::Empty, image.body);
+        assert_eq!("p".to_string(), document.sections[1].name);
+    }
+
+    #[test]
+    pub fn test_body_presence_comes_from_registry() {
+        let mut registry = SectionRegistry::default();
+        registry.extend(vec![crate::registry::SectionSpec {
+            name: "spacer".to_string(),
+            kind: BodyKind::Paragraph,
+            body: BodyPresence::None,
+            docs: "".to_string(),
+            example: "".to_string(),
+            attributes: vec![],
+            deprecated: false,
+        }]);
+        let source = "-- p\n\n-- spacer\n\nAlfa\n\n-- spacer\n\n-- hr";
+        let document = nom_parse(source, &registry).unwrap().1;
+        let left = vec![
+            ErrorNode {
+                span: 0..4,
+                kind: ErrorKind::InvalidSection {
+                    name: "p".to_string(),
+                    name_span: 3..4,
+                },
+            },
+            ErrorNode {
+                span: 6..21,
+                kind: ErrorKind::InvalidSection {
+                    name: "spacer".to_string(),
+                    name_span: 9..15,
+                },
+            },
+        ];
+        assert_eq!(left, document.errors);
+        let left = vec!["spacer".to_string(), "hr".to_string()];
+        let right = document
+            .sections
+            .iter()
+            .map(|s| s.name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    pub fn test_crlf_line_endings() {
+        let lf = "-- title\n-- id: alfa\n\nBravo charlie\ndelta\n\n-- list\n\n- echo\n\n- foxtrot\n\n-- code\n\nfn main() {}\n\n-- div/\n\n-- hr\n\n-- /div\n";
+        let crlf = lf.replace('\n', "\r\n");
+        let document = parse(&crlf);
+        assert_eq!(Vec::<ErrorNode>::new(), document.errors);
+        let names = |document: &Document| {
+            document
+                .all_sections()
+                .iter()
+                .map(|s| s.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&parse(lf)), names(&document));
+        let title = &document.sections[0];
+        assert_eq!(
+            "alfa".to_string(),
+            title.attributes[0].value.as_ref().unwrap().text
+        );
+        let words = title.paragraphs()[0]
+            .content
+            .iter()
+            .map(|inline| match inline {
+                Inline::Word(word) => word.text.clone(),
+                Inline::Tag(_) => "".to_string(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["Bravo", "charlie", "delta"], words);
+    }
+
+    #[test]
+    pub fn test_single_letter_first_word() {
+        let source = Span::new("I am");
+        let left = Paragraph {
+            span: 0..4,
+            content: vec![w("I", 0), w("am", 2)],
+        };
+        let right = paragraph(source).unwrap().1;
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    pub fn test_sections_without_attributes() {
+        let source = "-- notes\n\n- Alfa\n\n-- code\n\nBravo";
+        let document = parse(source);
+        assert_eq!(Vec::<ErrorNode>::new(), document.errors);
+        let left = vec!["notes".to_string(), "code".to_string()];
+        let right = document
+            .sections
+            .iter()
+            .map(|s| s.name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    pub fn test_registry_sections_are_parsed() {
+        let mut registry = SectionRegistry::default();
+        registry.extend(vec![crate::registry::SectionSpec {
+            name: "callout".to_string(),
+            kind: BodyKind::Paragraph,
+            body: BodyPresence::Required,
+            docs: "".to_string(),
+            example: "".to_string(),
+            attributes: vec![],
+            deprecated: false,
+        }]);
+        let source = "-- callout\n\nAlfa";
+        let document = nom_parse(source, &registry).unwrap().1;
+        assert_eq!(Vec::<ErrorNode>::new(), document.errors);
+        assert_eq!("callout".to_string(), document.sections[0].name);
+        let document = parse(source);
+        assert_eq!(0, document.sections.len());
+    }
+
+    // INLINE TAGS
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    pub fn test_inline_tag() {
+        let source = Span::new("<<alfa|strong>>");
+        let left = InlineTag {
+            span: 0..15,
+            params: vec![
+                TagParam {
+                    span: 2..6,
+                    content: vec![w("alfa", 2)],
+                },
+                TagParam {
+                    span: 7..13,
+                    content: vec![w("strong", 7)],
+                },
+            ],
+            pipes: vec![6..7],
+        };
+        let right = inline_tag(source).unwrap().1;
+        assert_eq!(left, right);
+        assert_eq!("strong".to_string(), right.name().unwrap().text());
+        assert_eq!("alfa".to_string(), right.text().unwrap().text());
+    }
+
+    #[test]
+    pub fn test_inline_tag_with_name_first() {
+        let source = Span::new("<<link|alfa bravo|https://www.example.com/>>");
+        let right = inline_tag(source).unwrap().1;
+        assert_eq!("link".to_string(), right.name().unwrap().text());
+        assert_eq!("alfa bravo".to_string(), right.text().unwrap().text());
+        assert_eq!(
+            "https://www.example.com/".to_string(),
+            right.attributes()[0].text()
+        );
+    }
+
+    #[test]
+    pub fn test_nested_multi_line_inline_tag() {
+        let source = Span::new("<<alfa <<bravo|em>>\ncharlie|strong>>");
+        let right = inline_tag(source).unwrap().1;
+        assert_eq!(0..36, right.span);
+        assert_eq!(
+            "alfa bravo charlie".to_string(),
+            right.text().unwrap().text()
+        );
+        match &right.params[0].content[1] {
+            Inline::Tag(inner) => assert_eq!(7..19, inner.span),
+            _ => panic!("expected a nested tag"),
+        }
+    }
+
+    #[test]
+    pub fn test_paragraph_with_inline_tags() {
+        let source = Span::new("alfa <<bravo|em>>, charlie<<delta|b>>");
+        let right = paragraph(source).unwrap().1;
+        assert_eq!(0..37, right.span);
+        assert_eq!(5, right.content.len());
+        assert_eq!("alfa bravo, charliedelta".to_string(), right.text());
+    }
+
+    #[test]
+    pub fn test_unclosed_inline_tag_is_an_error_node() {
+        let source = "-- p\n\nalfa <<bravo|em\n\n-- p\n\ncharlie";
+        let document = parse(source);
+        let left = vec![ErrorNode {
+            span: 11..21,
+            kind: ErrorKind::UnclosedInlineTag { open: 11..13 },
+        }];
+        assert_eq!(left, document.errors);
+        assert_eq!(2, document.sections.len());
+    }
+
+    #[test]
+    pub fn test_block_section() {
+        let source = "-- div/\n-- class: alfa\n\n-- p\n\nbravo\n\n-- /div\n\n-- p\n\ncharlie";
+        let document = parse(source);
+        assert_eq!(Vec::<ErrorNode>::new(), document.errors);
+        assert_eq!(2, document.sections.len());
+        let div = &document.sections[0];
+        assert_eq!("div".to_string(), div.name);
+        assert_eq!(0..44, div.span);
+        assert_eq!(
+            "alfa".to_string(),
+            div.attribute("class").unwrap().value.as_ref().unwrap().text
+        );
+        let block = div.block.as_ref().unwrap();
+        assert_eq!(6..7, block.slash);
+        assert_eq!(1, block.children.len());
+        assert_eq!(
+            "bravo".to_string(),
+            block.children[0].paragraphs()[0].text()
+        );
+        let left = BlockEnd {
+            span: 37..44,
+            dashes: 37..39,
+            slash: 40..41,
+            name: "div".to_string(),
+            name_span: 41..44,
+        };
+        assert_eq!(Some(left), block.end);
+    }
+
+    #[test]
+    pub fn test_nested_block_sections() {
+        let source = "-- div/\n\n-- div/\n\n-- h2\n\nalfa\n\n-- /div\n\n-- p\n\nbravo\n\n-- /div";
+        let document = parse(source);
+        assert_eq!(Vec::<ErrorNode>::new(), document.errors);
+        assert_eq!(1, document.sections.len());
+        let outer = &document.sections[0];
+        assert_eq!(2, outer.children().len());
+        assert_eq!(1, outer.children()[0].children().len());
+        assert_eq!(
+            vec!["div", "div", "h2", "p"],
+            document
+                .all_sections()
+                .iter()
+                .map(|section| section.name.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    pub fn test_unclosed_block_section() {
+        let source = "-- div/\n\n-- p\n\nalfa";
+        let document = parse(source);
+        let left = vec![ErrorNode {
+            span: 0..7,
+            kind: ErrorKind::UnclosedBlock {
+                name: "div".to_string(),
+                name_span: 3..6,
+            },
+        }];
+        assert_eq!(left, document.errors);
+        assert_eq!(0..19, document.sections[0].span);
+        assert_eq!(1, document.sections[0].children().len());
+    }
+
+    #[test]
+    pub fn test_mismatched_block_end() {
+        let source = "-- div/\n\n-- p\n\nalfa\n\n-- /aside";
+        let document = parse(source);
+        let left = vec![ErrorNode {
+            span: 21..30,
+            kind: ErrorKind::MismatchedBlockEnd {
+                expected: "div".to_string(),
+                found: "aside".to_string(),
+                name_span: 25..30,
+            },
+        }];
+        assert_eq!(left, document.errors);
+        assert_eq!(1, document.sections.len());
+    }
+
+    #[test]
+    pub fn test_unexpected_block_end() {
+        let source = "-- p\n\nalfa\n\n-- /div\n\n-- p\n\nbravo";
+        let document = parse(source);
+        let left = vec![ErrorNode {
+            span: 12..19,
+            kind: ErrorKind::UnexpectedBlockEnd {
+                name: "div".to_string(),
+                name_span: 16..19,
+            },
+        }];
+        assert_eq!(left, document.errors);
+        assert_eq!(2, document.sections.len());
+    }
 }
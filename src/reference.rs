@@ -24,7 +24,7 @@ pub fn get_reference(
     //     }
     // }
     let mut kv_list = ast.iter().collect::<Vec<_>>();
-    kv_list.sort_by(|a, b| a.1.name.start().cmp(&b.1.name.start()));
+    kv_list.sort_by_key(|a| a.1.name.start());
     let mut reference_symbol = ReferenceSymbol::Founding(ident_offset);
     // let mut fn_vector = Vector::new();
     for (_, v) in kv_list {
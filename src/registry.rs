@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::config::Config;
+
+// The section types the parser knows about. The
+// built in ones come from `default_sections.json`
+// and a workspace can add its own (or override
+// the defaults) in its config file.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyKind {
+    Paragraph,
+    List,
+    Raw,
+    Metadata,
+}
+
+// Whether a paragraph section needs a body. `-- hr`
+// can go without one and so can an `-- image`
+// without a caption.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyPresence {
+    #[default]
+    Required,
+    Optional,
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SectionSpec {
+    pub name: String,
+    pub kind: BodyKind,
+    #[serde(default)]
+    pub body: BodyPresence,
+    #[serde(default)]
+    pub docs: String,
+    #[serde(default)]
+    pub example: String,
+    #[serde(default)]
+    pub attributes: Vec<AttributeSpec>,
+    // Still parsed but shown as deprecated so it can
+    // be phased out
+    #[serde(default)]
+    pub deprecated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AttributeSpec {
+    pub name: String,
+    #[serde(default)]
+    pub docs: String,
+    // Values are picked from a known set. Completion
+    // offers the values used across the workspace.
+    #[serde(default)]
+    pub enumerated: bool,
+    #[serde(default)]
+    pub required: bool,
+    // The value is a file. Completion lists files
+    // from the asset directories, limited to these
+    // extensions when there are any.
+    #[serde(default)]
+    pub path: bool,
+    #[serde(default)]
+    pub extensions: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SectionRegistry {
+    sections: BTreeMap<String, SectionSpec>,
+}
+
+impl Default for SectionRegistry {
+    fn default() -> Self {
+        let defaults: Config = serde_json::from_str(include_str!("default_sections.json"))
+            .expect("default_sections.json is valid");
+        let mut registry = SectionRegistry {
+            sections: BTreeMap::new(),
+        };
+        registry.extend(defaults.sections);
+        registry
+    }
+}
+
+impl SectionRegistry {
+    pub fn extend(&mut self, specs: Vec<SectionSpec>) {
+        specs.into_iter().for_each(|spec| {
+            self.sections.insert(spec.name.clone(), spec);
+        });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SectionSpec> {
+        self.sections.get(name)
+    }
+
+    pub fn sections(&self) -> impl Iterator<Item = &SectionSpec> {
+        self.sections.values()
+    }
+}
+
+impl SectionSpec {
+    pub fn attribute(&self, name: &str) -> Option<&AttributeSpec> {
+        self.attributes.iter().find(|attr| attr.name == name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    pub fn test_default_sections() {
+        let registry = SectionRegistry::default();
+        assert_eq!(BodyKind::Paragraph, registry.get("h2").unwrap().kind);
+        assert_eq!(BodyKind::List, registry.get("notes").unwrap().kind);
+        assert_eq!(BodyKind::Raw, registry.get("code").unwrap().kind);
+        assert_eq!(BodyKind::Metadata, registry.get("metadata").unwrap().kind);
+        assert_eq!(BodyPresence::Required, registry.get("p").unwrap().body);
+        assert_eq!(BodyPresence::Optional, registry.get("image").unwrap().body);
+        assert_eq!(None, registry.get("titel"));
+    }
+
+    #[test]
+    pub fn test_extend_from_config() {
+        let config: Config = serde_json::from_str(
+            r#"{ "sections": [{ "name": "callout", "kind": "list", "attributes": [{ "name": "kind" }] }] }"#,
+        )
+        .unwrap();
+        let mut registry = SectionRegistry::default();
+        registry.extend(config.sections);
+        let spec = registry.get("callout").unwrap();
+        assert_eq!(BodyKind::List, spec.kind);
+        assert_eq!("kind".to_string(), spec.attributes[0].name);
+        assert!(registry.get("title").is_some());
+    }
+}
@@ -0,0 +1,178 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::jump_definition::link_at;
+use crate::neo_ast::Document;
+use crate::workspace::WorkspaceIndex;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImCompleteRenameTarget {
+    // The page that owns the id
+    pub page: PathBuf,
+    pub id: String,
+    // The id under the cursor, either in `-- id:` or
+    // inside a link
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImCompleteTextEdit {
+    pub path: PathBuf,
+    pub span: Range<usize>,
+    pub new_text: String,
+}
+
+// Only page ids can be renamed. That's the value of
+// `-- id:` in the metadata or a link to a known id.
+pub fn prepare_rename(
+    document: &Document,
+    path: &Path,
+    offset: usize,
+    index: &WorkspaceIndex,
+) -> Option<ImCompleteRenameTarget> {
+    if let Some(id) = document
+        .metadata_id()
+        .filter(|id| id.span.start <= offset && offset <= id.span.end)
+    {
+        return Some(ImCompleteRenameTarget {
+            page: path.to_path_buf(),
+            id: id.text.clone(),
+            span: id.span.clone(),
+        });
+    }
+    let target = link_at(document, offset)?.link_target()?;
+    let id = target.text();
+    let (page, _) = index.pages_with_id(&id).into_iter().next()?;
+    Some(ImCompleteRenameTarget {
+        page,
+        id,
+        span: target.span.clone(),
+    })
+}
+
+// One edit for the id itself and one for every link
+// that uses it. Links by path are left alone since
+// they don't change.
+pub fn rename_id(
+    target: &ImCompleteRenameTarget,
+    new_id: &str,
+    index: &WorkspaceIndex,
+) -> Result<Vec<ImCompleteTextEdit>, String> {
+    if new_id.is_empty() || new_id.contains(|c: char| c.is_whitespace() || "|<>".contains(c)) {
+        return Err(format!("`{}` can't be used as an id", new_id));
+    }
+    if let Some((path, _)) = index
+        .pages_with_id(new_id)
+        .into_iter()
+        .find(|(path, _)| *path != target.page)
+    {
+        return Err(format!(
+            "`{}` is already the id of {}",
+            new_id,
+            path.display()
+        ));
+    }
+    let mut edits = index
+        .pages_with_id(&target.id)
+        .into_iter()
+        .filter(|(path, _)| *path == target.page)
+        .map(|(path, span)| ImCompleteTextEdit {
+            path,
+            span,
+            new_text: new_id.to_string(),
+        })
+        .collect::<Vec<_>>();
+    index.pages().into_iter().for_each(|path| {
+        let Some(source) = index.get(&path) else {
+            return;
+        };
+        source
+            .document
+            .inline_tags()
+            .into_iter()
+            .filter_map(|tag| tag.link_target())
+            .filter(|link| link.text() == target.id)
+            .for_each(|link| {
+                edits.push(ImCompleteTextEdit {
+                    path: path.clone(),
+                    span: link.span.clone(),
+                    new_text: new_id.to_string(),
+                })
+            });
+    });
+    edits.sort_by(|a, b| a.path.cmp(&b.path).then(a.span.start.cmp(&b.span.start)));
+    Ok(edits)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nom_parser::nom_parse;
+    use crate::registry::SectionRegistry;
+    use pretty_assertions::assert_eq;
+
+    fn workspace() -> WorkspaceIndex {
+        let registry = SectionRegistry::default();
+        let index = WorkspaceIndex::default();
+        index.insert(
+            PathBuf::from("/site/alfa.neo"),
+            "-- metadata\n-- id: 2w3eqltz",
+            &registry,
+        );
+        index.insert(
+            PathBuf::from("/site/bravo.neo"),
+            "-- metadata\n-- id: bravo\n\n-- p\n\n<<link|alfa|2w3eqltz>> <<link|alfa|alfa.neo>>",
+            &registry,
+        );
+        index
+    }
+
+    #[test]
+    pub fn test_prepare_rename() {
+        let index = workspace();
+        let bravo = Path::new("/site/bravo.neo");
+        let document = index.get(bravo).unwrap().document.clone();
+        let left = ImCompleteRenameTarget {
+            page: bravo.to_path_buf(),
+            id: "bravo".to_string(),
+            span: 19..24,
+        };
+        assert_eq!(Some(left), prepare_rename(&document, bravo, 20, &index));
+        let left = ImCompleteRenameTarget {
+            page: PathBuf::from("/site/alfa.neo"),
+            id: "2w3eqltz".to_string(),
+            span: 44..52,
+        };
+        assert_eq!(Some(left), prepare_rename(&document, bravo, 35, &index));
+        assert_eq!(None, prepare_rename(&document, bravo, 60, &index));
+        assert_eq!(None, prepare_rename(&document, bravo, 4, &index));
+    }
+
+    #[test]
+    pub fn test_rename_id() {
+        let index = workspace();
+        let alfa = Path::new("/site/alfa.neo");
+        let document = nom_parse("-- metadata\n-- id: 2w3eqltz", &SectionRegistry::default())
+            .unwrap()
+            .1;
+        let target = prepare_rename(&document, alfa, 20, &index).unwrap();
+        let left = vec![
+            ImCompleteTextEdit {
+                path: alfa.to_path_buf(),
+                span: 19..27,
+                new_text: "charlie".to_string(),
+            },
+            ImCompleteTextEdit {
+                path: PathBuf::from("/site/bravo.neo"),
+                span: 44..52,
+                new_text: "charlie".to_string(),
+            },
+        ];
+        assert_eq!(Ok(left), rename_id(&target, "charlie", &index));
+        assert_eq!(
+            Err("`bravo` is already the id of /site/bravo.neo".to_string()),
+            rename_id(&target, "bravo", &index)
+        );
+        assert!(rename_id(&target, "two words", &index).is_err());
+    }
+}
@@ -1,10 +1,42 @@
-use std::collections::HashMap;
+use std::ops::Range;
 
-use tower_lsp::lsp_types::{SemanticTokenType};
+use tower_lsp::lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokensEdit,
+    SemanticTokensLegend,
+};
 
-use crate::chumsky::{Expr, Func, ImCompleteSemanticToken, Spanned};
+use crate::document_symbol::heading_level;
+use crate::line_index::LineIndex;
+use crate::neo_ast::{
+    Attribute, Document, ErrorKind, Inline, InlineTag, Paragraph, RawBody, Section, SectionBody,
+};
+use crate::registry::SectionRegistry;
 
+#[derive(Debug)]
+pub struct ImCompleteSemanticToken {
+    pub start: usize,
+    pub length: usize,
+    pub token_type: usize,
+    pub token_modifiers: u32,
+}
+
+pub const SECTION_NAME: SemanticTokenType = SemanticTokenType::new("sectionName");
+pub const ATTRIBUTE_KEY: SemanticTokenType = SemanticTokenType::new("attributeKey");
+pub const ATTRIBUTE_VALUE: SemanticTokenType = SemanticTokenType::new("attributeValue");
+pub const INLINE_TAG: SemanticTokenType = SemanticTokenType::new("inlineTag");
+pub const HEADING: SemanticTokenType = SemanticTokenType::new("heading");
+pub const RAW_CONTENT: SemanticTokenType = SemanticTokenType::new("rawContent");
+
+// The Neopolitan types come first. The stock types
+// after them are for punctuation, inline text and
+// the old nrs tokens.
 pub const LEGEND_TYPE: &[SemanticTokenType] = &[
+    SECTION_NAME,
+    ATTRIBUTE_KEY,
+    ATTRIBUTE_VALUE,
+    INLINE_TAG,
+    HEADING,
+    RAW_CONTENT,
     SemanticTokenType::CLASS,
     SemanticTokenType::COMMENT,
     SemanticTokenType::DECORATOR,
@@ -30,86 +62,488 @@ pub const LEGEND_TYPE: &[SemanticTokenType] = &[
     SemanticTokenType::VARIABLE,
 ];
 
-pub fn semantic_token_from_ast(ast: &HashMap<String, Func>) -> Vec<ImCompleteSemanticToken> {
-    let mut semantic_tokens = vec![];
+// `level1` to `level6` go on `h1` to `h6`
+pub const LEGEND_MODIFIER: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::new("level1"),
+    SemanticTokenModifier::new("level2"),
+    SemanticTokenModifier::new("level3"),
+    SemanticTokenModifier::new("level4"),
+    SemanticTokenModifier::new("level5"),
+    SemanticTokenModifier::new("level6"),
+    SemanticTokenModifier::DEPRECATED,
+    SemanticTokenModifier::new("invalid"),
+];
 
-    ast.iter().for_each(|(_func_name, function)| {
-        function.args.iter().for_each(|(_, span)| {
-            semantic_tokens.push(ImCompleteSemanticToken {
-                start: span.start,
-                length: span.len(),
-                token_type: LEGEND_TYPE
-                    .iter()
-                    .position(|item| item == &SemanticTokenType::PARAMETER)
-                    .unwrap(),
-            });
-        });
-        let (_, span) = &function.name;
-        semantic_tokens.push(ImCompleteSemanticToken {
-            start: span.start,
-            length: span.len(),
-            token_type: LEGEND_TYPE
-                .iter()
-                .position(|item| item == &SemanticTokenType::FUNCTION)
-                .unwrap(),
-        });
-        semantic_token_from_expr(&function.body, &mut semantic_tokens);
-    });
+// What a client that doesn't list the Neopolitan
+// types gets in their place. The indexes don't
+// change, only the names in the legend.
+pub fn fallback_token_type(token_type: &SemanticTokenType) -> SemanticTokenType {
+    match token_type.as_str() {
+        "sectionName" => SemanticTokenType::CLASS,
+        "attributeKey" => SemanticTokenType::PROPERTY,
+        "attributeValue" => SemanticTokenType::STRING,
+        "inlineTag" => SemanticTokenType::MACRO,
+        "heading" => SemanticTokenType::NAMESPACE,
+        "rawContent" => SemanticTokenType::STRING,
+        _ => token_type.clone(),
+    }
+}
 
+// The custom types are only used when the client
+// says it knows all of them
+pub fn legend(client_types: &[SemanticTokenType]) -> SemanticTokensLegend {
+    let custom = LEGEND_TYPE
+        .iter()
+        .filter(|token_type| fallback_token_type(token_type) != **token_type)
+        .all(|token_type| client_types.contains(token_type));
+    SemanticTokensLegend {
+        token_types: LEGEND_TYPE
+            .iter()
+            .map(|token_type| match custom {
+                true => token_type.clone(),
+                false => fallback_token_type(token_type),
+            })
+            .collect(),
+        token_modifiers: LEGEND_MODIFIER.to_vec(),
+    }
+}
+
+pub fn semantic_tokens_from_document(
+    document: &Document,
+    registry: &SectionRegistry,
+) -> Vec<ImCompleteSemanticToken> {
+    let mut semantic_tokens = vec![];
+    document
+        .sections
+        .iter()
+        .for_each(|section| semantic_tokens_from_section(section, registry, &mut semantic_tokens));
+    semantic_tokens_from_errors(document, &mut semantic_tokens);
+    semantic_tokens.sort_by_key(|token| token.start);
     semantic_tokens
 }
 
-pub fn semantic_token_from_expr(
-    expr: &Spanned<Expr>,
+fn semantic_tokens_from_section(
+    section: &Section,
+    registry: &SectionRegistry,
     semantic_tokens: &mut Vec<ImCompleteSemanticToken>,
 ) {
-    match &expr.0 {
-        Expr::Error => {}
-        Expr::Value(_) => {}
-        Expr::List(_) => {}
-        Expr::Local((_name, span)) => {
-            semantic_tokens.push(ImCompleteSemanticToken {
-                start: span.start,
-                length: span.len(),
-                token_type: LEGEND_TYPE
-                    .iter()
-                    .position(|item| item == &SemanticTokenType::VARIABLE)
-                    .unwrap(),
-            });
-        }
-        Expr::Let(_, rhs, rest, name_span) => {
-            semantic_tokens.push(ImCompleteSemanticToken {
-                start: name_span.start,
-                length: name_span.len(),
-                token_type: LEGEND_TYPE
-                    .iter()
-                    .position(|item| item == &SemanticTokenType::VARIABLE)
-                    .unwrap(),
+    let level = heading_level(&section.name);
+    let (name_type, name_modifiers) = match level {
+        Some(level) => (HEADING, modifier(&format!("level{}", level))),
+        None if section.name == "title" => (HEADING, 0),
+        None => (SECTION_NAME, 0),
+    };
+    let name_modifiers = match registry.get(&section.name) {
+        Some(spec) if spec.deprecated => name_modifiers | modifier("deprecated"),
+        _ => name_modifiers,
+    };
+    semantic_tokens.push(neo_token(&section.dashes, &SemanticTokenType::DECORATOR, 0));
+    semantic_tokens.push(neo_token(&section.name_span, &name_type, name_modifiers));
+    if let Some(block) = &section.block {
+        semantic_tokens.push(neo_token(&block.slash, &SemanticTokenType::DECORATOR, 0));
+    }
+    section
+        .attributes
+        .iter()
+        .for_each(|attr| semantic_tokens_from_attribute(attr, semantic_tokens));
+    // Heading text gets the same type and level as the
+    // heading name so themes can size it
+    let (word_type, word_modifiers) = match name_type == HEADING {
+        true => (HEADING, name_modifiers),
+        false => (SemanticTokenType::STRING, 0),
+    };
+    match &section.body {
+        SectionBody::Empty => {}
+        SectionBody::Paragraphs(paragraphs) => paragraphs.iter().for_each(|p| {
+            semantic_tokens_from_paragraph(p, &word_type, word_modifiers, semantic_tokens)
+        }),
+        SectionBody::List(items) => items.iter().for_each(|item| {
+            semantic_tokens.push(neo_token(&item.dash, &SemanticTokenType::DECORATOR, 0));
+            item.paragraphs.iter().for_each(|p| {
+                semantic_tokens_from_paragraph(p, &word_type, word_modifiers, semantic_tokens)
             });
-            semantic_token_from_expr(rhs, semantic_tokens);
-            semantic_token_from_expr(rest, semantic_tokens);
-        }
-        Expr::Then(first, rest) => {
-            semantic_token_from_expr(first, semantic_tokens);
-            semantic_token_from_expr(rest, semantic_tokens);
+        }),
+        SectionBody::Raw(raw) => semantic_tokens_from_raw(raw, semantic_tokens),
+    }
+    if let Some(block) = &section.block {
+        block
+            .children
+            .iter()
+            .for_each(|child| semantic_tokens_from_section(child, registry, semantic_tokens));
+        if let Some(end) = &block.end {
+            semantic_tokens.push(neo_token(&end.dashes, &SemanticTokenType::DECORATOR, 0));
+            semantic_tokens.push(neo_token(&end.slash, &SemanticTokenType::DECORATOR, 0));
+            semantic_tokens.push(neo_token(&end.name_span, &name_type, name_modifiers));
         }
-        Expr::Binary(lhs, _op, rhs) => {
-            semantic_token_from_expr(lhs, semantic_tokens);
-            semantic_token_from_expr(rhs, semantic_tokens);
+    }
+}
+
+// Errors mark the token already at their spot as
+// invalid. Unknown section names don't have one yet
+// so they get a new token.
+fn semantic_tokens_from_errors(
+    document: &Document,
+    semantic_tokens: &mut Vec<ImCompleteSemanticToken>,
+) {
+    document.errors.iter().for_each(|error| {
+        let (span, token_type) = match &error.kind {
+            ErrorKind::UnknownSection { name_span, .. }
+            | ErrorKind::MissingBlankLine { name_span, .. }
+            | ErrorKind::InvalidSection { name_span, .. }
+            | ErrorKind::UnclosedBlock { name_span, .. }
+            | ErrorKind::MismatchedBlockEnd { name_span, .. }
+            | ErrorKind::UnexpectedBlockEnd { name_span, .. } => (name_span, SECTION_NAME),
+            ErrorKind::UnclosedInlineTag { open } => (open, SemanticTokenType::OPERATOR),
+            ErrorKind::InvalidAttribute { key_span, .. } => (key_span, ATTRIBUTE_KEY),
+            ErrorKind::UnexpectedContent => return,
+        };
+        match semantic_tokens
+            .iter_mut()
+            .find(|token| token.start == span.start)
+        {
+            Some(token) => token.token_modifiers |= modifier("invalid"),
+            None => semantic_tokens.push(neo_token(span, &token_type, modifier("invalid"))),
         }
-        Expr::Call(expr, params) => {
-            semantic_token_from_expr(expr, semantic_tokens);
-            params.0.iter().for_each(|p| {
-                semantic_token_from_expr(p, semantic_tokens);
-            });
+    });
+}
+
+// Tokens can't run across lines so the raw body
+// gets one per non-empty line
+fn semantic_tokens_from_raw(raw: &RawBody, semantic_tokens: &mut Vec<ImCompleteSemanticToken>) {
+    let mut start = raw.span.start;
+    raw.text.split_inclusive('\n').for_each(|line| {
+        let length = line.trim_end_matches(['\r', '\n']).len();
+        if length > 0 {
+            semantic_tokens.push(neo_token(&(start..start + length), &RAW_CONTENT, 0));
         }
-        Expr::If(test, consequent, alternative) => {
-            semantic_token_from_expr(test, semantic_tokens);
-            semantic_token_from_expr(consequent, semantic_tokens);
-            semantic_token_from_expr(alternative, semantic_tokens);
+        start += line.len();
+    });
+}
+
+fn semantic_tokens_from_attribute(
+    attr: &Attribute,
+    semantic_tokens: &mut Vec<ImCompleteSemanticToken>,
+) {
+    semantic_tokens.push(neo_token(&attr.dashes, &SemanticTokenType::DECORATOR, 0));
+    semantic_tokens.push(neo_token(&attr.key_span, &ATTRIBUTE_KEY, 0));
+    if let Some(value) = &attr.value {
+        semantic_tokens.push(neo_token(&value.colon, &SemanticTokenType::OPERATOR, 0));
+        semantic_tokens.push(neo_token(&value.span, &ATTRIBUTE_VALUE, 0));
+    }
+}
+
+fn semantic_tokens_from_paragraph(
+    paragraph: &Paragraph,
+    word_type: &SemanticTokenType,
+    word_modifiers: u32,
+    semantic_tokens: &mut Vec<ImCompleteSemanticToken>,
+) {
+    semantic_tokens_from_inlines(
+        &paragraph.content,
+        word_type,
+        word_modifiers,
+        semantic_tokens,
+    );
+}
+
+fn semantic_tokens_from_inlines(
+    content: &[Inline],
+    word_type: &SemanticTokenType,
+    word_modifiers: u32,
+    semantic_tokens: &mut Vec<ImCompleteSemanticToken>,
+) {
+    content.iter().for_each(|item| match item {
+        Inline::Word(word) => {
+            semantic_tokens.push(neo_token(&word.span, word_type, word_modifiers))
         }
-        Expr::Print(expr) => {
-            semantic_token_from_expr(expr, semantic_tokens);
+        Inline::Tag(tag) => semantic_tokens_from_tag(tag, semantic_tokens),
+    });
+}
+
+fn semantic_tokens_from_tag(tag: &InlineTag, semantic_tokens: &mut Vec<ImCompleteSemanticToken>) {
+    let name = tag.name();
+    let text = tag.text();
+    let mut delimiters = tag.pipes.clone();
+    delimiters.push(tag.span.start..tag.span.start + 2);
+    delimiters.push(tag.span.end - 2..tag.span.end);
+    delimiters.iter().for_each(|span| {
+        semantic_tokens.push(neo_token(span, &SemanticTokenType::OPERATOR, 0));
+    });
+    tag.params.iter().for_each(|param| {
+        let word_type = if name.is_some_and(|n| std::ptr::eq(n, param)) {
+            INLINE_TAG
+        } else if text.is_some_and(|t| std::ptr::eq(t, param)) {
+            SemanticTokenType::STRING
+        } else {
+            SemanticTokenType::PARAMETER
+        };
+        semantic_tokens_from_inlines(&param.content, &word_type, 0, semantic_tokens);
+    });
+}
+
+// Each token is relative to the one before it, so
+// `tokens` has to be sorted. Tokens never cross a
+// line so the length is the difference in columns.
+pub fn encode_semantic_tokens(
+    tokens: &[ImCompleteSemanticToken],
+    index: &LineIndex,
+) -> Vec<SemanticToken> {
+    let mut pre_line = 0;
+    let mut pre_start = 0;
+    tokens
+        .iter()
+        .filter_map(|token| {
+            let start_position = index.position(token.start)?;
+            let end_position = index.position(token.start + token.length)?;
+            let line = start_position.line;
+            let start = start_position.character;
+            let delta_line = line - pre_line;
+            let delta_start = if delta_line == 0 {
+                start - pre_start
+            } else {
+                start
+            };
+            pre_line = line;
+            pre_start = start;
+            Some(SemanticToken {
+                delta_line,
+                delta_start,
+                length: end_position.character - start,
+                token_type: token.token_type as u32,
+                token_modifiers_bitset: token.token_modifiers,
+            })
+        })
+        .collect()
+}
+
+// The tokens that overlap `span`. `tokens` has to be
+// sorted, which lets this skip straight to them
+// instead of walking the whole document.
+pub fn tokens_in_range(
+    tokens: &[ImCompleteSemanticToken],
+    span: Range<usize>,
+) -> &[ImCompleteSemanticToken] {
+    let first = tokens.partition_point(|token| token.start + token.length <= span.start);
+    let last = tokens.partition_point(|token| token.start < span.end);
+    &tokens[first..last.max(first)]
+}
+
+// One edit that swaps out everything between the
+// tokens the two results start and end with. Edit
+// offsets count integers and every token is five.
+pub fn semantic_tokens_edits(
+    previous: &[SemanticToken],
+    current: &[SemanticToken],
+) -> Vec<SemanticTokensEdit> {
+    if previous == current {
+        return vec![];
+    }
+    let prefix = previous
+        .iter()
+        .zip(current)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = previous[prefix..]
+        .iter()
+        .rev()
+        .zip(current[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    vec![SemanticTokensEdit {
+        start: (prefix * 5) as u32,
+        delete_count: ((previous.len() - prefix - suffix) * 5) as u32,
+        data: Some(current[prefix..current.len() - suffix].to_vec()),
+    }]
+}
+
+fn neo_token(
+    span: &Range<usize>,
+    token_type: &SemanticTokenType,
+    token_modifiers: u32,
+) -> ImCompleteSemanticToken {
+    ImCompleteSemanticToken {
+        start: span.start,
+        length: span.len(),
+        token_type: LEGEND_TYPE
+            .iter()
+            .position(|item| item == token_type)
+            .unwrap(),
+        token_modifiers,
+    }
+}
+
+fn modifier(name: &str) -> u32 {
+    LEGEND_MODIFIER
+        .iter()
+        .position(|item| item.as_str() == name)
+        .map(|index| 1 << index)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::line_index::PositionEncoding;
+    use crate::nom_parser::nom_parse;
+    use crate::registry::SectionRegistry;
+    use pretty_assertions::assert_eq;
+    use ropey::Rope;
+
+    fn encode(source: &str) -> Vec<SemanticToken> {
+        let registry = SectionRegistry::default();
+        let document = nom_parse(source, &registry).unwrap().1;
+        let tokens = semantic_tokens_from_document(&document, &registry);
+        let rope = Rope::from_str(source);
+        encode_semantic_tokens(&tokens, &LineIndex::new(&rope, PositionEncoding::Utf16))
+    }
+
+    fn token(
+        delta_line: u32,
+        delta_start: u32,
+        length: u32,
+        token_type: &SemanticTokenType,
+    ) -> SemanticToken {
+        SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type: LEGEND_TYPE.iter().position(|t| t == token_type).unwrap() as u32,
+            token_modifiers_bitset: 0,
         }
     }
+
+    // The modifiers of the name token of each section
+    fn name_modifiers(source: &str, registry: &SectionRegistry) -> Vec<(usize, u32)> {
+        let document = nom_parse(source, registry).unwrap().1;
+        let tokens = semantic_tokens_from_document(&document, registry);
+        document
+            .all_sections()
+            .into_iter()
+            .filter_map(|section| {
+                let token = tokens
+                    .iter()
+                    .find(|token| token.start == section.name_span.start)?;
+                Some((token.token_type, token.token_modifiers))
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn test_encode_semantic_tokens() {
+        let left = vec![
+            token(0, 0, 2, &SemanticTokenType::DECORATOR),
+            token(0, 3, 1, &SECTION_NAME),
+            token(2, 0, 2, &SemanticTokenType::STRING),
+            token(0, 3, 4, &SemanticTokenType::STRING),
+        ];
+        assert_eq!(left, encode("-- p\n\n😀 alfa"));
+    }
+
+    #[test]
+    pub fn test_tokens_in_range() {
+        let source = "-- p\n\nalfa bravo\n\n-- p\n\ncharlie";
+        let registry = SectionRegistry::default();
+        let document = nom_parse(source, &registry).unwrap().1;
+        let tokens = semantic_tokens_from_document(&document, &registry);
+        let starts = |span| {
+            tokens_in_range(&tokens, span)
+                .iter()
+                .map(|token| token.start)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![11, 18], starts(13..21));
+        assert_eq!(Vec::<usize>::new(), starts(16..17));
+        assert_eq!(vec![24], starts(25..40));
+    }
+
+    #[test]
+    pub fn test_semantic_tokens_edits() {
+        // Tokens are relative so `delta` encodes the same
+        // as `bravo` and only the last two change
+        let previous = encode("-- p\n\nalfa bravo charlie");
+        let current = encode("-- p\n\nalfa delta echo charlie");
+        let left = vec![SemanticTokensEdit {
+            start: 20,
+            delete_count: 5,
+            data: Some(current[4..6].to_vec()),
+        }];
+        assert_eq!(left, semantic_tokens_edits(&previous, &current));
+        assert_eq!(
+            Vec::<SemanticTokensEdit>::new(),
+            semantic_tokens_edits(&current, &current)
+        );
+    }
+
+    #[test]
+    pub fn test_heading_tokens() {
+        let level2 = SemanticToken {
+            token_modifiers_bitset: modifier("level2"),
+            ..token(0, 3, 2, &HEADING)
+        };
+        let left = vec![
+            token(0, 0, 2, &SemanticTokenType::DECORATOR),
+            level2,
+            SemanticToken {
+                delta_line: 2,
+                delta_start: 0,
+                length: 4,
+                ..level2
+            },
+        ];
+        assert_eq!(left, encode("-- h2\n\nAlfa"));
+    }
+
+    #[test]
+    pub fn test_attribute_and_raw_tokens() {
+        let left = vec![
+            token(0, 0, 2, &SemanticTokenType::DECORATOR),
+            token(0, 3, 4, &SECTION_NAME),
+            token(1, 0, 2, &SemanticTokenType::DECORATOR),
+            token(0, 3, 4, &ATTRIBUTE_KEY),
+            token(0, 4, 1, &SemanticTokenType::OPERATOR),
+            token(0, 2, 4, &ATTRIBUTE_VALUE),
+            token(2, 0, 8, &RAW_CONTENT),
+        ];
+        assert_eq!(left, encode("-- code\n-- rust: alfa\n\nfn bravo"));
+    }
+
+    #[test]
+    pub fn test_deprecated_and_invalid_modifiers() {
+        let mut registry = SectionRegistry::default();
+        registry.extend(vec![crate::registry::SectionSpec {
+            name: "callout".to_string(),
+            kind: crate::registry::BodyKind::Paragraph,
+            body: crate::registry::BodyPresence::Required,
+            docs: "".to_string(),
+            example: "".to_string(),
+            attributes: vec![],
+            deprecated: true,
+        }]);
+        let source = "-- callout\n\nalfa\n\n-- div/\n\n-- p\n\nbravo";
+        let left = vec![
+            (0, modifier("deprecated")),
+            (0, modifier("invalid")),
+            (0, 0),
+        ];
+        assert_eq!(left, name_modifiers(source, &registry));
+    }
+
+    #[test]
+    pub fn test_legend() {
+        let fallback = legend(&[]);
+        assert_eq!(LEGEND_TYPE.len(), fallback.token_types.len());
+        assert_eq!(SemanticTokenType::CLASS, fallback.token_types[0]);
+        assert_eq!(SemanticTokenType::PROPERTY, fallback.token_types[1]);
+        assert_eq!(LEGEND_MODIFIER.to_vec(), fallback.token_modifiers);
+        let custom = legend(&[
+            SECTION_NAME,
+            ATTRIBUTE_KEY,
+            ATTRIBUTE_VALUE,
+            INLINE_TAG,
+            HEADING,
+            RAW_CONTENT,
+        ]);
+        assert_eq!(LEGEND_TYPE.to_vec(), custom.token_types);
+        assert_eq!(fallback.token_types, legend(&[SECTION_NAME]).token_types);
+    }
 }
@@ -0,0 +1,144 @@
+use ropey::Rope;
+use tower_lsp::lsp_types::TextDocumentContentChangeEvent;
+
+use crate::line_index::{LineIndex, PositionEncoding};
+
+// Applies the changes from a `didChange` in the
+// order they were sent. Each range is relative to
+// the text after the previous change. Returns `None`
+// without touching anything if any range doesn't fit
+// so the caller can resync instead of keeping a
+// half edited document.
+pub fn apply_changes(
+    rope: &Rope,
+    changes: &[TextDocumentContentChangeEvent],
+    encoding: PositionEncoding,
+) -> Option<Rope> {
+    let mut response = rope.clone();
+    for change in changes {
+        match change.range {
+            Some(range) => {
+                let index = LineIndex::new(&response, encoding);
+                let start = response.byte_to_char(index.offset(range.start)?);
+                let end = response.byte_to_char(index.offset(range.end)?);
+                if start > end {
+                    return None;
+                }
+                response.remove(start..end);
+                response.insert(start, &change.text);
+            }
+            None => response = Rope::from_str(&change.text),
+        }
+    }
+    Some(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tower_lsp::lsp_types::{Position, Range};
+
+    fn change(range: (u32, u32, u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(
+                Position::new(range.0, range.1),
+                Position::new(range.2, range.3),
+            )),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    pub fn test_apply_changes_in_order() {
+        let rope = Rope::from_str("-- title\n\nAlfa\n");
+        let changes = vec![
+            change((2, 0, 2, 4), "Bravo"),
+            change((2, 5, 2, 5), "\n\n-- p\n\nCharlie"),
+            change((0, 3, 0, 8), "h2"),
+        ];
+        assert_eq!(
+            "-- h2\n\nBravo\n\n-- p\n\nCharlie\n".to_string(),
+            apply_changes(&rope, &changes, PositionEncoding::Utf16)
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[test]
+    pub fn test_apply_full_change() {
+        let rope = Rope::from_str("-- title\n\nAlfa");
+        let changes = vec![
+            TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "-- p\n\nBravo".to_string(),
+            },
+            change((2, 5, 2, 5), "!"),
+        ];
+        assert_eq!(
+            "-- p\n\nBravo!".to_string(),
+            apply_changes(&rope, &changes, PositionEncoding::Utf16)
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[test]
+    pub fn test_apply_changes_out_of_range() {
+        let rope = Rope::from_str("-- title\n\nAlfa");
+        assert_eq!(
+            "-- title\n\nAlfa!".to_string(),
+            apply_changes(
+                &rope,
+                &[change((2, 10, 2, 10), "!")],
+                PositionEncoding::Utf16
+            )
+            .unwrap()
+            .to_string()
+        );
+        assert_eq!(
+            None,
+            apply_changes(&rope, &[change((9, 0, 9, 0), "!")], PositionEncoding::Utf16)
+        );
+        assert_eq!(
+            None,
+            apply_changes(&rope, &[change((2, 2, 2, 0), "!")], PositionEncoding::Utf16)
+        );
+    }
+
+    #[test]
+    pub fn test_apply_changes_after_unicode_separators() {
+        let rope = Rope::from_str("-- p\n\nalfa\u{2028}bravo\x0Ccharlie\n\ndelta");
+        let changes = vec![
+            change((2, 11, 2, 18), "echo"),
+            change((4, 0, 4, 5), "foxtrot"),
+        ];
+        assert_eq!(
+            "-- p\n\nalfa\u{2028}bravo\x0Cecho\n\nfoxtrot".to_string(),
+            apply_changes(&rope, &changes, PositionEncoding::Utf16)
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[test]
+    pub fn test_apply_changes_after_emoji() {
+        let rope = Rope::from_str("-- p\n\n😀 alfa");
+        let changes = vec![change((2, 3, 2, 7), "bravo")];
+        assert_eq!(
+            "-- p\n\n😀 bravo".to_string(),
+            apply_changes(&rope, &changes, PositionEncoding::Utf16)
+                .unwrap()
+                .to_string()
+        );
+        let changes = vec![change((2, 5, 2, 9), "bravo")];
+        assert_eq!(
+            "-- p\n\n😀 bravo".to_string(),
+            apply_changes(&rope, &changes, PositionEncoding::Utf8)
+                .unwrap()
+                .to_string()
+        );
+    }
+}
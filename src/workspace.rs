@@ -0,0 +1,413 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+use dashmap::mapref::one::Ref;
+use dashmap::DashMap;
+use ropey::Rope;
+use tower_lsp::lsp_types::SymbolKind;
+
+use crate::document_symbol::heading_level;
+use crate::neo_ast::Document;
+use crate::nom_parser::nom_parse;
+use crate::registry::SectionRegistry;
+
+// Every `.neo` file under the workspace folders,
+// parsed. Open documents are kept in here too so
+// lookups across pages see unsaved changes.
+
+pub const NEO_EXTENSION: &str = "neo";
+
+#[derive(Debug)]
+pub struct IndexedPage {
+    pub rope: Rope,
+    pub document: Document,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImCompleteWorkspaceSymbol {
+    pub path: PathBuf,
+    pub name: String,
+    pub kind: SymbolKind,
+    pub span: Range<usize>,
+    pub container: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct WorkspaceIndex {
+    pages: DashMap<PathBuf, IndexedPage>,
+    // Whether linked files exist, so diagnostics don't
+    // go to the disk on every keystroke. Watched file
+    // events clear the entries that changed.
+    files: DashMap<PathBuf, bool>,
+}
+
+impl WorkspaceIndex {
+    pub fn insert(&self, path: PathBuf, text: &str, registry: &SectionRegistry) {
+        let document = match nom_parse(text, registry) {
+            Ok((_, document)) => document,
+            _ => Document::default(),
+        };
+        self.insert_document(path, Rope::from_str(text), document);
+    }
+
+    pub fn insert_document(&self, path: PathBuf, rope: Rope, document: Document) {
+        self.pages.insert(path, IndexedPage { rope, document });
+    }
+
+    pub fn remove(&self, path: &Path) {
+        self.pages.remove(path);
+    }
+
+    // Drops everything under a workspace folder
+    // that's been removed
+    pub fn remove_root(&self, root: &Path) {
+        self.pages.retain(|path, _| !path.starts_with(root));
+    }
+
+    pub fn file_exists(&self, path: &Path) -> bool {
+        *self
+            .files
+            .entry(path.to_path_buf())
+            .or_insert_with(|| path.exists())
+    }
+
+    // Also drops everything under it in case it was
+    // a directory
+    pub fn forget_file(&self, path: &Path) {
+        self.files.retain(|file, _| !file.starts_with(path));
+    }
+
+    pub fn get(&self, path: &Path) -> Option<Ref<'_, PathBuf, IndexedPage>> {
+        self.pages.get(path)
+    }
+
+    pub fn pages(&self) -> Vec<PathBuf> {
+        self.pages.iter().map(|page| page.key().clone()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    // Reads and parses every page under `root`. Files
+    // that can't be read are skipped.
+    pub fn scan(&self, root: &Path, registry: &SectionRegistry) {
+        neo_files(root).into_iter().for_each(|path| {
+            if let Ok(text) = std::fs::read_to_string(&path) {
+                self.insert(path, &text, registry);
+            }
+        });
+    }
+
+    // Every value used for `-- key:` in sections
+    // named `section` across the workspace
+    pub fn attribute_values(&self, section: &str, key: &str) -> BTreeSet<String> {
+        self.pages
+            .iter()
+            .flat_map(|page| {
+                page.document
+                    .all_sections()
+                    .into_iter()
+                    .filter(|s| s.name == section)
+                    .filter_map(|s| s.attribute(key)?.value.as_ref())
+                    .map(|value| value.text.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    // Where each page with the given metadata id
+    // declares it
+    pub fn pages_with_id(&self, id: &str) -> Vec<(PathBuf, Range<usize>)> {
+        let mut response = self
+            .pages
+            .iter()
+            .filter_map(|page| {
+                let value = page.document.metadata_id()?;
+                (value.text == id).then(|| (page.key().clone(), value.span.clone()))
+            })
+            .collect::<Vec<_>>();
+        response.sort_by(|a, b| a.0.cmp(&b.0));
+        response
+    }
+
+    // Every page whose id is also used by another page
+    pub fn pages_with_duplicate_ids(&self) -> HashSet<PathBuf> {
+        let mut ids: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        self.pages.iter().for_each(|page| {
+            if let Some(value) = page.document.metadata_id() {
+                ids.entry(value.text.clone())
+                    .or_default()
+                    .push(page.key().clone());
+            }
+        });
+        ids.into_values()
+            .filter(|paths| paths.len() > 1)
+            .flatten()
+            .collect()
+    }
+
+    // Page titles, headings and ids whose name
+    // fuzzy matches the query
+    pub fn symbols(&self, query: &str) -> Vec<ImCompleteWorkspaceSymbol> {
+        let mut response = self
+            .pages
+            .iter()
+            .flat_map(|page| page_symbols(page.key(), &page.document))
+            .filter(|symbol| fuzzy_match(query, &symbol.name))
+            .collect::<Vec<_>>();
+        response.sort_by(|a, b| a.path.cmp(&b.path).then(a.span.start.cmp(&b.span.start)));
+        response
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImCompleteDuplicateId {
+    pub id: String,
+    pub span: Range<usize>,
+    pub others: Vec<(PathBuf, Range<usize>)>,
+}
+
+pub fn duplicate_id(path: &Path, index: &WorkspaceIndex) -> Option<ImCompleteDuplicateId> {
+    let (id, span) = {
+        let page = index.get(path)?;
+        let value = page.document.metadata_id()?;
+        (value.text.clone(), value.span.clone())
+    };
+    let others = index
+        .pages_with_id(&id)
+        .into_iter()
+        .filter(|(other, _)| other != path)
+        .collect::<Vec<_>>();
+    (!others.is_empty()).then_some(ImCompleteDuplicateId { id, span, others })
+}
+
+pub fn is_neo_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == NEO_EXTENSION)
+}
+
+// Resolves `..` and `.` without touching the file
+// system so it works for pages that don't exist
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut response = PathBuf::new();
+    path.components().for_each(|component| match component {
+        Component::CurDir => {}
+        Component::ParentDir => {
+            response.pop();
+        }
+        _ => response.push(component),
+    });
+    response
+}
+
+// Hidden directories like `.git` are skipped
+pub fn neo_files(root: &Path) -> Vec<PathBuf> {
+    let mut response = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        entries.flatten().for_each(|entry| {
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() && !hidden => dirs.push(path),
+                Ok(file_type) if file_type.is_file() && is_neo_file(&path) => response.push(path),
+                _ => {}
+            }
+        });
+    }
+    response.sort();
+    response
+}
+
+pub fn page_symbols(path: &Path, document: &Document) -> Vec<ImCompleteWorkspaceSymbol> {
+    let title = document.title().and_then(|section| {
+        section
+            .paragraphs()
+            .first()
+            .map(|p| (p.text(), p.span.clone()))
+    });
+    let mut response = vec![];
+    if let Some((name, span)) = &title {
+        response.push(ImCompleteWorkspaceSymbol {
+            path: path.to_path_buf(),
+            name: name.clone(),
+            kind: SymbolKind::FILE,
+            span: span.clone(),
+            container: None,
+        });
+    }
+    let container = title.map(|(name, _)| name);
+    if let Some(id) = document.metadata_id() {
+        response.push(ImCompleteWorkspaceSymbol {
+            path: path.to_path_buf(),
+            name: id.text.clone(),
+            kind: SymbolKind::KEY,
+            span: id.span.clone(),
+            container: container.clone(),
+        });
+    }
+    document
+        .all_sections()
+        .into_iter()
+        .filter(|section| heading_level(&section.name).is_some())
+        .filter_map(|section| section.paragraphs().first().copied())
+        .for_each(|paragraph| {
+            response.push(ImCompleteWorkspaceSymbol {
+                path: path.to_path_buf(),
+                name: paragraph.text(),
+                kind: SymbolKind::NAMESPACE,
+                span: paragraph.span.clone(),
+                container: container.clone(),
+            })
+        });
+    response
+}
+
+// Case insensitive. The query characters have to
+// show up in order but not next to each other.
+pub fn fuzzy_match(query: &str, name: &str) -> bool {
+    let mut chars = name.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .filter(|c| !c.is_whitespace())
+        .all(|q| chars.any(|c| c == q))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    pub fn test_fuzzy_match() {
+        assert!(fuzzy_match("", "Alfa"));
+        assert!(fuzzy_match("abr", "Alfa Bravo"));
+        assert!(fuzzy_match("AB", "alfa bravo"));
+        assert!(!fuzzy_match("ob", "Alfa Bravo Charlie"));
+    }
+
+    #[test]
+    pub fn test_workspace_symbols() {
+        let index = WorkspaceIndex::default();
+        let registry = SectionRegistry::default();
+        index.insert(
+            PathBuf::from("/site/alfa.neo"),
+            "-- title\n\nAlfa Page\n\n-- h2\n\nBravo\n\n-- metadata\n-- id: 1234abcd",
+            &registry,
+        );
+        index.insert(
+            PathBuf::from("/site/charlie.neo"),
+            "-- title\n\nCharlie",
+            &registry,
+        );
+        let left = vec![
+            ImCompleteWorkspaceSymbol {
+                path: PathBuf::from("/site/alfa.neo"),
+                name: "Alfa Page".to_string(),
+                kind: SymbolKind::FILE,
+                span: 10..19,
+                container: None,
+            },
+            ImCompleteWorkspaceSymbol {
+                path: PathBuf::from("/site/alfa.neo"),
+                name: "Bravo".to_string(),
+                kind: SymbolKind::NAMESPACE,
+                span: 28..33,
+                container: Some("Alfa Page".to_string()),
+            },
+            ImCompleteWorkspaceSymbol {
+                path: PathBuf::from("/site/alfa.neo"),
+                name: "1234abcd".to_string(),
+                kind: SymbolKind::KEY,
+                span: 54..62,
+                container: Some("Alfa Page".to_string()),
+            },
+            ImCompleteWorkspaceSymbol {
+                path: PathBuf::from("/site/charlie.neo"),
+                name: "Charlie".to_string(),
+                kind: SymbolKind::FILE,
+                span: 10..17,
+                container: None,
+            },
+        ];
+        assert_eq!(left, index.symbols(""));
+        assert_eq!(
+            vec!["Bravo".to_string()],
+            index
+                .symbols("brv")
+                .into_iter()
+                .map(|symbol| symbol.name)
+                .collect::<Vec<_>>()
+        );
+        index.remove(Path::new("/site/alfa.neo"));
+        assert_eq!(1, index.symbols("").len());
+    }
+
+    #[test]
+    pub fn test_duplicate_ids() {
+        let index = WorkspaceIndex::default();
+        let registry = SectionRegistry::default();
+        let alfa = PathBuf::from("/site/alfa.neo");
+        let bravo = PathBuf::from("/site/bravo.neo");
+        let charlie = PathBuf::from("/site/charlie.neo");
+        index.insert(alfa.clone(), "-- metadata\n-- id: abcd1234", &registry);
+        index.insert(bravo.clone(), "-- metadata\n-- id: abcd1234", &registry);
+        index.insert(charlie.clone(), "-- metadata\n-- id: wxyz6789", &registry);
+        let left = ImCompleteDuplicateId {
+            id: "abcd1234".to_string(),
+            span: 19..27,
+            others: vec![(bravo.clone(), 19..27)],
+        };
+        assert_eq!(Some(left), duplicate_id(&alfa, &index));
+        assert_eq!(None, duplicate_id(&charlie, &index));
+        assert_eq!(
+            HashSet::from([alfa.clone(), bravo.clone()]),
+            index.pages_with_duplicate_ids()
+        );
+        index.insert(bravo, "-- metadata\n-- id: efgh5678", &registry);
+        assert_eq!(None, duplicate_id(&alfa, &index));
+        assert!(index.pages_with_duplicate_ids().is_empty());
+    }
+
+    #[test]
+    pub fn test_file_exists_is_cached() {
+        let dir =
+            std::env::temp_dir().join(format!("neopolitan-file-exists-{}", std::process::id()));
+        let file = dir.join("alfa.jpg");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&file, "").unwrap();
+        let index = WorkspaceIndex::default();
+        assert!(index.file_exists(&file));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(index.file_exists(&file));
+        index.forget_file(&dir);
+        assert!(!index.file_exists(&file));
+    }
+
+    #[test]
+    pub fn test_duplicate_ids_ignore_trailing_whitespace() {
+        let index = WorkspaceIndex::default();
+        let registry = SectionRegistry::default();
+        let alfa = PathBuf::from("/site/alfa.neo");
+        let bravo = PathBuf::from("/site/bravo.neo");
+        index.insert(alfa.clone(), "-- metadata\n-- id: abcd1234  ", &registry);
+        index.insert(bravo.clone(), "-- metadata\n-- id: abcd1234\t", &registry);
+        assert_eq!(
+            HashSet::from([alfa.clone(), bravo.clone()]),
+            index.pages_with_duplicate_ids()
+        );
+        assert_eq!(
+            vec![(alfa, 19..27), (bravo, 19..27)],
+            index.pages_with_id("abcd1234")
+        );
+    }
+}